@@ -0,0 +1,613 @@
+use std::sync::Arc;
+
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, B256, I256, U256, address, uint},
+    providers::Provider,
+    sol,
+    sol_types::SolCall,
+};
+use dashmap::DashMap;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::datafetcher::{ADDR_BATCH, Call, IMulticall2, MULTICALL2, load_token};
+use crate::engine::Token;
+use crate::pool::{AmmPool, Protocol, whole_tokens};
+
+sol! {
+    #[sol(rpc)]
+    interface IBalancerVault {
+        function getPoolTokens(bytes32 poolId)
+            returns (address[] tokens, uint256[] balances, uint256 lastChangeBlock);
+    }
+
+    #[sol(rpc)]
+    interface IWeightedPool {
+        function getPoolId() returns (bytes32);
+        function getNormalizedWeights() returns (uint256[]);
+        function getSwapFeePercentage() returns (uint256);
+    }
+}
+
+/// Balancer V2 Vault: every swap routes through it by pool ID
+pub const BALANCER_VAULT: Address = address!("BA12222222228d8Ba445958a75a0704d566BF2C8");
+/// 80/20 BAL/WETH weighted pool
+pub const BALANCER_80BAL_20WETH: Address = address!("5c6Ee304399DBdB9C8Ef030aB642B10820DB8F56");
+
+/// WeightedMath: a swap may not take in more than 30% of the balance
+const MAX_IN_RATIO: u64 = 300_000_000_000_000_000;
+/// FixedPoint.powUp error bound: 1e-14, in 1e18 fixed point
+const MAX_POW_RELATIVE_ERROR: u64 = 10_000;
+
+fn one() -> U256 {
+    U256::from(10u64.pow(18))
+}
+
+/// -------------------------------
+/// Pool model
+/// -------------------------------
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalancerPool {
+    pub id: String,      // pool contract address
+    pub pool_id: String, // bytes32 Vault pool ID, for routing swaps
+    pub tokens: Vec<Token>,
+    pub balances: Vec<U256>, // raw token units
+    pub weights: Vec<U256>,  // normalized, 1e18 = 100%
+    pub swap_fee: U256,      // 1e18 = 100%
+}
+
+impl BalancerPool {
+    /// Fee as a fraction (0.003 for 0.3%)
+    pub fn fee_fraction(&self) -> f64 {
+        f64::from(self.swap_fee) / 1e18
+    }
+
+    pub fn index_of(&self, token_id: &str) -> Option<usize> {
+        self.tokens.iter().position(|t| t.id == token_id)
+    }
+
+    /// Sized quote, as `WeightedPool.onSwap` (GIVEN_IN) computes it: fee
+    /// off the raw input, upscale to 18 decimals, `calcOutGivenIn`, then
+    /// downscale rounding down.
+    ///
+    /// Every step, `powUp` included, is the Vault's integer math, so the
+    /// quote is the amount the Vault pays out to the unit.
+    pub fn calc_out_given_in(&self, i: usize, j: usize, amount_in: U256) -> Option<U256> {
+        let n = self.tokens.len();
+        if i == j || i >= n || j >= n || amount_in.is_zero() {
+            return None;
+        }
+
+        let fee_amount = mul_up(amount_in, self.swap_fee)?;
+        let amount_in = amount_in.checked_sub(fee_amount)?;
+
+        let scale_in = self.scaling(i)?;
+        let scale_out = self.scaling(j)?;
+        let balance_in = self.balances[i].checked_mul(scale_in)?;
+        let balance_out = self.balances[j].checked_mul(scale_out)?;
+        let amount_in = amount_in.checked_mul(scale_in)?;
+
+        if amount_in > mul_down(balance_in, U256::from(MAX_IN_RATIO))? {
+            return None;
+        }
+
+        let denominator = balance_in.checked_add(amount_in)?;
+        let base = div_up(balance_in, denominator)?;
+        let exponent = div_down(self.weights[i], self.weights[j])?;
+        let power = pow_up(base, exponent)?;
+        let amount_out = mul_down(balance_out, complement(power))?;
+
+        Some(amount_out / scale_out)
+    }
+
+    /// 10^(18 - decimals)
+    fn scaling(&self, i: usize) -> Option<U256> {
+        let decimals = u32::from(self.tokens[i].decimals);
+        Some(U256::from(10).pow(U256::from(18u32.checked_sub(decimals)?)))
+    }
+}
+
+impl AmmPool for BalancerPool {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::Balancer
+    }
+
+    fn tokens(&self) -> Vec<&Token> {
+        self.tokens.iter().collect()
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee_fraction()
+    }
+
+    fn holdings(&self) -> Option<Vec<f64>> {
+        Some(
+            self.balances
+                .iter()
+                .zip(&self.tokens)
+                .map(|(b, t)| whole_tokens(f64::from(*b), t))
+                .collect(),
+        )
+    }
+
+    /// (B_out / W_out) / (B_in / W_in) in raw units, less the fee
+    fn spot_rate(&self, token_in: &str, token_out: &str) -> Option<f64> {
+        let i = self.index_of(token_in)?;
+        let j = self.index_of(token_out)?;
+        let b_in = f64::from(self.balances[i]);
+        let b_out = f64::from(self.balances[j]);
+        let w_in = f64::from(self.weights[i]);
+        let w_out = f64::from(self.weights[j]);
+        let rate = (b_out / w_out) / (b_in / w_in) * (1.0 - self.fee_fraction());
+        (rate.is_finite() && rate > 0.0).then_some(rate)
+    }
+
+    fn quote(&self, amount_in: U256, token_in: &str, token_out: &str) -> Option<U256> {
+        self.calc_out_given_in(
+            self.index_of(token_in)?,
+            self.index_of(token_out)?,
+            amount_in,
+        )
+    }
+}
+
+/// -------------------------------
+/// FixedPoint (1e18) helpers
+/// -------------------------------
+fn mul_down(a: U256, b: U256) -> Option<U256> {
+    Some(a.checked_mul(b)? / one())
+}
+
+fn mul_up(a: U256, b: U256) -> Option<U256> {
+    let product = a.checked_mul(b)?;
+    if product.is_zero() {
+        Some(U256::ZERO)
+    } else {
+        Some((product - U256::from(1)) / one() + U256::from(1))
+    }
+}
+
+fn div_down(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    Some(a.checked_mul(one())? / b)
+}
+
+fn div_up(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    if a.is_zero() {
+        return Some(U256::ZERO);
+    }
+    Some((a.checked_mul(one())? - U256::from(1)) / b + U256::from(1))
+}
+
+fn complement(x: U256) -> U256 {
+    if x < one() { one() - x } else { U256::ZERO }
+}
+
+/// FixedPoint.powUp
+fn pow_up(x: U256, y: U256) -> Option<U256> {
+    if y == one() {
+        Some(x)
+    } else if y == one() * U256::from(2) {
+        mul_up(x, x)
+    } else if y == one() * U256::from(4) {
+        let square = mul_up(x, x)?;
+        mul_up(square, square)
+    } else {
+        let raw = pow(x, y)?;
+        let max_error = mul_up(raw, U256::from(MAX_POW_RELATIVE_ERROR))? + U256::from(1);
+        raw.checked_add(max_error)
+    }
+}
+
+/// -------------------------------
+/// LogExpMath (signed 1e18 fixed point)
+/// -------------------------------
+/// Port of Balancer's `LogExpMath`, with its rounding: `exp` and `ln`
+/// reduce by the e^(2^k) table below, then sum a short series in 1e20
+/// fixed point; `ln` of values near 1 uses a 1e36 series instead.
+const ONE_18: I256 = I256::from_raw(uint!(1000000000000000000_U256));
+const ONE_20: I256 = I256::from_raw(uint!(100000000000000000000_U256));
+const ONE_36: I256 = I256::from_raw(uint!(1000000000000000000000000000000000000_U256));
+/// exp is defined on [-41, 130]
+const MAX_NATURAL_EXPONENT: I256 = I256::from_raw(uint!(130000000000000000000_U256));
+const MIN_NATURAL_EXPONENT_ABS: I256 = I256::from_raw(uint!(41000000000000000000_U256));
+/// `pow` takes ln from the 1e36 series for bases in (0.9, 1.1)
+const LN_36_LOWER_BOUND: I256 = I256::from_raw(uint!(900000000000000000_U256));
+const LN_36_UPPER_BOUND: I256 = I256::from_raw(uint!(1100000000000000000_U256));
+
+/// 2^7 and 2^6 (1e18), with e^x as a whole number
+const X0: I256 = I256::from_raw(uint!(128000000000000000000_U256));
+const A0: I256 = I256::from_raw(uint!(
+    38877084059945950922200000000000000000000000000000000000_U256
+));
+const X1: I256 = I256::from_raw(uint!(64000000000000000000_U256));
+const A1: I256 = I256::from_raw(uint!(6235149080811616882910000000_U256));
+/// 2^5 down to 2^-4 and e^x, both in 1e20; `exp` stops at 2^-2
+const TERMS: [(I256, I256); 10] = [
+    (
+        I256::from_raw(uint!(3200000000000000000000_U256)),
+        I256::from_raw(uint!(7896296018268069516100000000000000_U256)),
+    ),
+    (
+        I256::from_raw(uint!(1600000000000000000000_U256)),
+        I256::from_raw(uint!(888611052050787263676000000_U256)),
+    ),
+    (
+        I256::from_raw(uint!(800000000000000000000_U256)),
+        I256::from_raw(uint!(298095798704172827474000_U256)),
+    ),
+    (
+        I256::from_raw(uint!(400000000000000000000_U256)),
+        I256::from_raw(uint!(5459815003314423907810_U256)),
+    ),
+    (
+        I256::from_raw(uint!(200000000000000000000_U256)),
+        I256::from_raw(uint!(738905609893065022723_U256)),
+    ),
+    (
+        I256::from_raw(uint!(100000000000000000000_U256)),
+        I256::from_raw(uint!(271828182845904523536_U256)),
+    ),
+    (
+        I256::from_raw(uint!(50000000000000000000_U256)),
+        I256::from_raw(uint!(164872127070012814685_U256)),
+    ),
+    (
+        I256::from_raw(uint!(25000000000000000000_U256)),
+        I256::from_raw(uint!(128402541668774148407_U256)),
+    ),
+    (
+        I256::from_raw(uint!(12500000000000000000_U256)),
+        I256::from_raw(uint!(113314845306682631683_U256)),
+    ),
+    (
+        I256::from_raw(uint!(6250000000000000000_U256)),
+        I256::from_raw(uint!(106449445891785942956_U256)),
+    ),
+];
+
+fn int(n: u64) -> I256 {
+    I256::from_raw(U256::from(n))
+}
+
+/// LogExpMath.pow: x^y as exp(y * ln x), both 1e18 fixed point
+fn pow(x: U256, y: U256) -> Option<U256> {
+    if y.is_zero() {
+        return Some(one());
+    }
+    if x.is_zero() {
+        return Some(U256::ZERO);
+    }
+    let mild_exponent_bound = (U256::from(1) << 254) / U256::from(10).pow(U256::from(20));
+    if x.bit(255) || y >= mild_exponent_bound {
+        return None;
+    }
+    let (x, y) = (I256::from_raw(x), I256::from_raw(y));
+
+    let logx_times_y = if LN_36_LOWER_BOUND < x && x < LN_36_UPPER_BOUND {
+        let ln_36_x = ln_36(x);
+        (ln_36_x / ONE_18).checked_mul(y)? + (ln_36_x % ONE_18).checked_mul(y)? / ONE_18
+    } else {
+        ln(x).checked_mul(y)?
+    };
+    let logx_times_y = logx_times_y / ONE_18;
+
+    Some(exp(logx_times_y)?.into_raw())
+}
+
+/// LogExpMath.exp: e^x, 1e18 fixed point
+fn exp(x: I256) -> Option<I256> {
+    if x > MAX_NATURAL_EXPONENT || -x > MIN_NATURAL_EXPONENT_ABS {
+        return None;
+    }
+    if x.is_negative() {
+        return Some(ONE_18 * ONE_18 / exp(-x)?);
+    }
+
+    let (x, first_an) = if x >= X0 {
+        (x - X0, A0)
+    } else if x >= X1 {
+        (x - X1, A1)
+    } else {
+        (x, I256::ONE)
+    };
+
+    // the rest in 1e20, as a product of the table's e^(2^k)
+    let mut x = x * int(100);
+    let mut product = ONE_20;
+    for (x_n, a_n) in &TERMS[..8] {
+        if x >= *x_n {
+            x = x - *x_n;
+            product = product * *a_n / ONE_20;
+        }
+    }
+
+    // what remains is under 2^-2: Taylor series to the 12th term
+    let mut series_sum = ONE_20;
+    let mut term = ONE_20;
+    for n in 1..=12 {
+        term = term * x / ONE_20 / int(n);
+        series_sum = series_sum + term;
+    }
+
+    Some(product * series_sum / ONE_20 * first_an / int(100))
+}
+
+/// LogExpMath._ln: ln(a) for a > 0, 1e18 fixed point
+fn ln(a: I256) -> I256 {
+    if a < ONE_18 {
+        return -ln(ONE_18 * ONE_18 / a);
+    }
+
+    let mut a = a;
+    let mut sum = I256::ZERO;
+    if a >= A0 * ONE_18 {
+        a = a / A0;
+        sum = sum + X0;
+    }
+    if a >= A1 * ONE_18 {
+        a = a / A1;
+        sum = sum + X1;
+    }
+
+    // the rest in 1e20, dividing out the table's e^(2^k)
+    let mut sum = sum * int(100);
+    let mut a = a * int(100);
+    for (x_n, a_n) in TERMS {
+        if a >= a_n {
+            a = a * ONE_20 / a_n;
+            sum = sum + x_n;
+        }
+    }
+
+    // ln(a) = 2 * atanh(z), z = (a - 1) / (a + 1)
+    let z = (a - ONE_20) * ONE_20 / (a + ONE_20);
+    let z_squared = z * z / ONE_20;
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3, 5, 7, 9, 11] {
+        num = num * z_squared / ONE_20;
+        series_sum = series_sum + num / int(n);
+    }
+
+    (sum + series_sum * int(2)) / int(100)
+}
+
+/// LogExpMath._ln_36: ln(x) for x near 1, in 1e36 fixed point
+fn ln_36(x: I256) -> I256 {
+    let x = x * ONE_18;
+    let z = (x - ONE_36) * ONE_36 / (x + ONE_36);
+    let z_squared = z * z / ONE_36;
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3, 5, 7, 9, 11, 13, 15] {
+        num = num * z_squared / ONE_36;
+        series_sum = series_sum + num / int(n);
+    }
+    series_sum * int(2)
+}
+
+/// -------------------------------
+/// On-chain loading (multicall)
+/// -------------------------------
+/// Load pool ID, normalized weights and swap fee from each pool, then
+/// tokens and balances from the Vault, all as of `block`. Pools that
+/// aren't weighted pools (no `getNormalizedWeights`) are skipped.
+pub async fn fetch_balancer_pools<P>(
+    provider: Arc<P>,
+    pool_addrs: &[Address],
+    cache: Arc<DashMap<Address, Token>>,
+    block: BlockId,
+) -> Result<Vec<BalancerPool>>
+where
+    P: Provider + 'static,
+{
+    let multicall = IMulticall2::new(MULTICALL2, provider.clone());
+    let mut pools = Vec::new();
+
+    for chunk in pool_addrs.chunks(ADDR_BATCH) {
+        let mut calls = Vec::with_capacity(chunk.len() * 3);
+        for &pool in chunk {
+            for data in [
+                IWeightedPool::getPoolIdCall.abi_encode(),
+                IWeightedPool::getNormalizedWeightsCall.abi_encode(),
+                IWeightedPool::getSwapFeePercentageCall.abi_encode(),
+            ] {
+                calls.push(Call {
+                    target: pool,
+                    callData: data.into(),
+                });
+            }
+        }
+        let res = multicall
+            .tryAggregate(false, calls)
+            .block(block)
+            .call()
+            .await?;
+
+        let mut configs: Vec<(Address, B256, Vec<U256>, U256)> = Vec::new();
+        for (k, &pool) in chunk.iter().enumerate() {
+            let r = &res[k * 3..k * 3 + 3];
+            if !r.iter().all(|c| c.success && !c.returnData.is_empty()) {
+                eprintln!("Skipping Balancer pool {:#x}: not a weighted pool", pool);
+                continue;
+            }
+            configs.push((
+                pool,
+                IWeightedPool::getPoolIdCall::abi_decode_returns(&r[0].returnData)?,
+                IWeightedPool::getNormalizedWeightsCall::abi_decode_returns(&r[1].returnData)?,
+                IWeightedPool::getSwapFeePercentageCall::abi_decode_returns(&r[2].returnData)?,
+            ));
+        }
+
+        let vault_calls: Vec<Call> = configs
+            .iter()
+            .map(|(_, pool_id, _, _)| Call {
+                target: BALANCER_VAULT,
+                callData: IBalancerVault::getPoolTokensCall { poolId: *pool_id }
+                    .abi_encode()
+                    .into(),
+            })
+            .collect();
+        let vault_res = multicall
+            .aggregate(vault_calls)
+            .block(block)
+            .call()
+            .await?
+            .returnData;
+
+        for ((pool, pool_id, weights, swap_fee), raw) in configs.into_iter().zip(vault_res.iter()) {
+            let state = IBalancerVault::getPoolTokensCall::abi_decode_returns(raw)?;
+            if state.tokens.len() != weights.len() {
+                eprintln!("Skipping Balancer pool {:#x}: token/weight mismatch", pool);
+                continue;
+            }
+
+            let mut tokens = Vec::with_capacity(state.tokens.len());
+            for &addr in &state.tokens {
+                match load_token(addr, provider.clone(), &cache, block).await {
+                    Ok(t) => tokens.push(t),
+                    Err(e) => {
+                        eprintln!("Error loading token {:#x} of {:#x}: {:?}", addr, pool, e);
+                        break;
+                    }
+                }
+            }
+            if tokens.len() != state.tokens.len() {
+                continue;
+            }
+
+            pools.push(BalancerPool {
+                id: format!("{:#x}", pool),
+                pool_id: format!("{:#x}", pool_id),
+                tokens,
+                balances: state.balances,
+                weights,
+                swap_fee,
+            });
+        }
+    }
+
+    Ok(pools)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::token_with_decimals;
+
+    fn pool(weights: [u64; 2], balances: [u128; 2], decimals: [u8; 2]) -> BalancerPool {
+        BalancerPool {
+            id: "bpt".to_string(),
+            pool_id: "0x01".to_string(),
+            tokens: vec![
+                token_with_decimals("a", decimals[0]),
+                token_with_decimals("b", decimals[1]),
+            ],
+            balances: balances.iter().map(|b| U256::from(*b)).collect(),
+            weights: weights
+                .iter()
+                .map(|w| U256::from(*w) * U256::from(10u64.pow(16)))
+                .collect(),
+            swap_fee: U256::from(3u64 * 10u64.pow(15)), // 0.3%
+        }
+    }
+
+    #[test]
+    fn fifty_fifty_matches_constant_product() {
+        let e18 = 10u128.pow(18);
+        let p = pool([50, 50], [1_000 * e18, 2_000 * e18], [18, 18]);
+        let amount = U256::from(10 * e18);
+        let out = f64::from(p.calc_out_given_in(0, 1, amount).unwrap()) / 1e18;
+
+        // x*y=k with the fee taken off the input
+        let x = 10.0 * 0.997;
+        let expected = 2_000.0 * x / (1_000.0 + x);
+        assert!((out - expected).abs() < 1e-9, "{out} vs {expected}");
+    }
+
+    #[test]
+    fn weighted_quote_approaches_spot_rate() {
+        let p = pool(
+            [80, 20],
+            [8_000_000 * 10u128.pow(18), 1_000 * 10u128.pow(6)],
+            [18, 6],
+        );
+        for (i, j) in [(0, 1), (1, 0)] {
+            let amount = p.balances[i] / U256::from(1_000_000);
+            let out = f64::from(p.calc_out_given_in(i, j, amount).unwrap());
+            let spot = p.spot_rate(&p.tokens[i].id, &p.tokens[j].id).unwrap() * f64::from(amount);
+            // within price impact plus one unit of downscale rounding
+            assert!(
+                out <= spot && spot - out < spot * 1e-5 + 1.0,
+                "{out} vs {spot}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_inputs_over_max_ratio() {
+        let e18 = 10u128.pow(18);
+        let p = pool([50, 50], [1_000 * e18, 1_000 * e18], [18, 18]);
+        assert!(p.calc_out_given_in(0, 1, U256::from(400 * e18)).is_none());
+        assert_eq!(p.edges().len(), 2);
+    }
+
+    #[test]
+    fn log_exp_pow_stays_within_pow_up_error_bound() {
+        // (x, y, floor of the exact x^y), 1e18 fixed point; the first
+        // takes ln from the 1e36 series
+        let cases: [(u128, u128, u128); 3] = [
+            (
+                990_000_000_000_000_000,
+                1_500_000_000_000_000_000,
+                985_037_562_735_553_755,
+            ),
+            (
+                500_000_000_000_000_000,
+                666_666_666_666_666_666,
+                629_960_524_947_436_582,
+            ),
+            (
+                2_000_000_000_000_000_000,
+                500_000_000_000_000_000,
+                1_414_213_562_373_095_048,
+            ),
+        ];
+        for (x, y, exact) in cases {
+            let (x, y, exact) = (U256::from(x), U256::from(y), U256::from(exact));
+            let raw = pow(x, y).unwrap();
+            assert!(
+                raw.abs_diff(exact) <= mul_up(exact, U256::from(MAX_POW_RELATIVE_ERROR)).unwrap()
+            );
+            // padded up, so a quote never beats the exact curve
+            assert!(pow_up(x, y).unwrap() > exact);
+        }
+        assert_eq!(exp(ONE_18).unwrap(), int(2_718_281_828_459_045_235));
+    }
+
+    #[test]
+    fn non_integer_weight_ratio_quotes_just_under_the_curve() {
+        let e18 = 10u128.pow(18);
+        // 60/40: exponent 1.5 takes the LogExpMath path
+        let p = pool([60, 40], [1_000 * e18, 2_000 * e18], [18, 18]);
+        let out = f64::from(p.calc_out_given_in(0, 1, U256::from(10 * e18)).unwrap());
+
+        // 2000 * (1 - (1000 / 1009.97)^1.5), exact: 29.541534264863551e18
+        let exact = 29_541_534_264_863_551_076.0;
+        assert!(
+            out <= exact && exact - out < exact * 1e-12,
+            "{out} vs {exact}"
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::Path;
+
+use alloy::{
+    network::{Ethereum, ReceiptResponse},
+    node_bindings::Anvil,
+    primitives::{Address, Bytes, U256},
+    providers::{PendingTransactionBuilder, Provider, ProviderBuilder},
+    sol,
+};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{DEFAULT_BASE_TOKENS, Pool};
+use crate::quote::get_amount_out;
+
+sol! {
+    #[sol(rpc)]
+    interface IWETH9 {
+        function deposit() external payable;
+    }
+
+    #[sol(rpc)]
+    interface IERC20Transfer {
+        function transfer(address to, uint256 value) returns (bool);
+        function balanceOf(address owner) returns (uint256);
+    }
+
+    #[sol(rpc)]
+    interface IUniswapV2PairSwap {
+        function getReserves()
+            returns (uint112 reserve0, uint112 reserve1, uint32);
+        function swap(uint amount0Out, uint amount1Out, address to, bytes data);
+    }
+}
+
+/// Allow/deny list file read by `TokenLists::load` when `ARB_TOKEN_LISTS`
+/// isn't set
+pub const TOKEN_LISTS_PATH: &str = "token_lists.json";
+
+/// Largest buy or sell tax a token may take and still be routed: any
+/// transfer fee breaks exact `getAmountOut` quoting
+pub const MAX_TRANSFER_TAX: f64 = 0.005;
+
+/// WETH spent on each simulated buy, at most 1% of the pair's WETH
+const PROBE_WEI: u128 = 100_000_000_000_000_000;
+
+/// -------------------------------
+/// Static token lists
+/// -------------------------------
+/// Tokens classified by hand:
+///
+/// ```json
+/// { "allow": ["0xc02a..."], "deny": ["0x..."] }
+/// ```
+///
+/// `allow` skips the honeypot check, `deny` keeps a token out regardless.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TokenLists {
+    #[serde(default)]
+    pub allow: HashSet<String>,
+    #[serde(default)]
+    pub deny: HashSet<String>,
+}
+
+impl TokenLists {
+    /// `ARB_TOKEN_LISTS` (or `token_lists.json` if present); empty lists
+    /// if neither exists
+    pub fn load() -> Result<Self> {
+        match env::var("ARB_TOKEN_LISTS") {
+            Ok(path) => Self::from_file(path),
+            Err(_) if Path::new(TOKEN_LISTS_PATH).exists() => Self::from_file(TOKEN_LISTS_PATH),
+            Err(_) => Ok(TokenLists::default()),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("reading {}: {}", path.display(), e))?;
+        Self::from_json(&json)
+    }
+
+    /// Addresses are lowercased to match `Token.id`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let lists: TokenLists = serde_json::from_str(json)?;
+        let lower = |set: HashSet<String>| set.into_iter().map(|a| a.to_lowercase()).collect();
+        Ok(TokenLists {
+            allow: lower(lists.allow),
+            deny: lower(lists.deny),
+        })
+    }
+}
+
+/// Outcome of a simulated buy-then-sell, as cached in the registry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Verdict {
+    /// Both legs went through; the share of each leg's expected output
+    /// that didn't arrive
+    Passed { buy_tax: f64, sell_tax: f64 },
+    /// A leg reverted or paid nothing out
+    Failed(String),
+}
+
+/// What becomes of a token no list or verdict covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UncheckedPolicy {
+    /// Kept out until it is allow-listed or passes a honeypot check
+    #[default]
+    Exclude,
+    /// Routed through as if it had passed
+    Allow,
+}
+
+/// -------------------------------
+/// Token classification
+/// -------------------------------
+/// Whether a token may be routed through: lists first, then its cached
+/// honeypot verdict, then `unchecked_policy` for tokens neither covers.
+#[derive(Debug, Clone)]
+pub struct TokenClassifier {
+    pub lists: TokenLists,
+    pub verdicts: HashMap<String, Verdict>, // token id -> verdict
+    pub max_tax: f64,
+    pub unchecked_policy: UncheckedPolicy,
+}
+
+impl TokenClassifier {
+    pub fn new(lists: TokenLists, verdicts: HashMap<String, Verdict>) -> Self {
+        TokenClassifier {
+            lists,
+            verdicts,
+            max_tax: MAX_TRANSFER_TAX,
+            unchecked_policy: UncheckedPolicy::default(),
+        }
+    }
+
+    /// On a list or with a verdict
+    pub fn is_classified(&self, token_id: &str) -> bool {
+        self.lists.allow.contains(token_id)
+            || self.lists.deny.contains(token_id)
+            || self.verdicts.contains_key(token_id)
+    }
+
+    pub fn tradable(&self, token_id: &str) -> bool {
+        if self.lists.deny.contains(token_id) {
+            return false;
+        }
+        if self.lists.allow.contains(token_id) {
+            return true;
+        }
+        match self.verdicts.get(token_id) {
+            Some(Verdict::Passed { buy_tax, sell_tax }) => buy_tax.max(*sell_tax) <= self.max_tax,
+            Some(Verdict::Failed(_)) => false,
+            None => self.unchecked_policy == UncheckedPolicy::Allow,
+        }
+    }
+
+    /// Tokens paired with WETH in `pools` that no list or verdict covers
+    /// yet, each with its deepest WETH pair to simulate through
+    pub fn unchecked<'a>(&self, pools: &'a [Pool]) -> Vec<(&'a str, &'a Pool)> {
+        let weth = DEFAULT_BASE_TOKENS[0];
+        let mut deepest: HashMap<&'a str, (f64, &'a Pool)> = HashMap::new();
+        for pool in pools {
+            let (token, weth_reserve) = if pool.token0.id == weth {
+                (&pool.token1.id, &pool.reserve0)
+            } else if pool.token1.id == weth {
+                (&pool.token0.id, &pool.reserve1)
+            } else {
+                continue;
+            };
+            if self.is_classified(token) {
+                continue;
+            }
+            let depth: f64 = weth_reserve.parse().unwrap_or(0.0);
+            match deepest.get(token.as_str()) {
+                Some(&(d, _)) if d >= depth => {}
+                _ => {
+                    deepest.insert(token, (depth, pool));
+                }
+            }
+        }
+        let mut unchecked: Vec<(&str, &Pool)> =
+            deepest.into_iter().map(|(t, (_, p))| (t, p)).collect();
+        unchecked.sort_by_key(|(t, _)| *t);
+        unchecked
+    }
+}
+
+/// -------------------------------
+/// Honeypot check
+/// -------------------------------
+/// Fork the chain at `block_number` on a local anvil and round-trip WETH
+/// through each of `targets` (token, WETH pair), as `unchecked` lists
+/// them. Tokens whose check fails for reasons other than the token itself
+/// (RPC errors) get no verdict.
+pub async fn check_tokens(
+    fork_url: &str,
+    block_number: u64,
+    targets: &[(&str, &Pool)],
+) -> Result<Vec<(String, Verdict)>> {
+    let anvil = Anvil::new()
+        .fork(fork_url)
+        .fork_block_number(block_number)
+        .try_spawn()?;
+    let provider = ProviderBuilder::new().connect_http(anvil.endpoint_url());
+    let trader = anvil.addresses()[0];
+    println!(
+        "🍯 Simulating {} tokens on a fork of block {}",
+        targets.len(),
+        block_number
+    );
+
+    let mut verdicts = Vec::with_capacity(targets.len());
+    for &(token, pool) in targets {
+        match simulate_round_trip(&provider, trader, pool).await {
+            Ok(verdict) => verdicts.push((token.to_string(), verdict)),
+            Err(e) => eprintln!("could not check token {}: {}", token, e),
+        }
+    }
+    Ok(verdicts)
+}
+
+/// Buy the pool's non-WETH token with WETH and sell it straight back,
+/// through the pair itself as the router would, and compare what arrived
+/// on each leg with what `getAmountOut` promised.
+pub async fn simulate_round_trip<P: Provider>(
+    provider: &P,
+    trader: Address,
+    pool: &Pool,
+) -> Result<Verdict> {
+    let weth: Address = DEFAULT_BASE_TOKENS[0].parse()?;
+    let weth_is_0 = pool.token0.id == DEFAULT_BASE_TOKENS[0];
+    let token = if weth_is_0 {
+        &pool.token1
+    } else {
+        &pool.token0
+    };
+    let token: Address = token.id.parse()?;
+    let pair_address: Address = pool.id.parse()?;
+    let pair = IUniswapV2PairSwap::new(pair_address, provider);
+    let weth_erc20 = IERC20Transfer::new(weth, provider);
+    let token_erc20 = IERC20Transfer::new(token, provider);
+
+    // (WETH, token) reserves at the fork
+    let reserves = || async {
+        let r = pair.getReserves().call().await?;
+        let (r0, r1) = (U256::from(r.reserve0), U256::from(r.reserve1));
+        eyre::Ok(if weth_is_0 { (r0, r1) } else { (r1, r0) })
+    };
+    // amounts out in pair order, WETH side first
+    let outs = |weth_out: U256, token_out: U256| {
+        if weth_is_0 {
+            (weth_out, token_out)
+        } else {
+            (token_out, weth_out)
+        }
+    };
+
+    // buy
+    let (weth_reserve, token_reserve) = reserves().await?;
+    let amount_in = U256::from(PROBE_WEI).min(weth_reserve / U256::from(100));
+    let Some(promised) = get_amount_out(amount_in, weth_reserve, token_reserve, pool.fee) else {
+        return Ok(Verdict::Failed("pair has no liquidity".to_string()));
+    };
+    IWETH9::new(weth, provider)
+        .deposit()
+        .value(amount_in)
+        .from(trader)
+        .send()
+        .await?
+        .get_receipt()
+        .await?;
+    weth_erc20
+        .transfer(pair_address, amount_in)
+        .from(trader)
+        .send()
+        .await?
+        .get_receipt()
+        .await?;
+    let held = token_erc20.balanceOf(trader).call().await?;
+    let (out0, out1) = outs(U256::ZERO, promised);
+    if !succeeded(
+        pair.swap(out0, out1, trader, Bytes::new())
+            .from(trader)
+            .send()
+            .await,
+    )
+    .await?
+    {
+        return Ok(Verdict::Failed("buy reverted".to_string()));
+    }
+    let bought = token_erc20
+        .balanceOf(trader)
+        .call()
+        .await?
+        .saturating_sub(held);
+    if bought.is_zero() {
+        return Ok(Verdict::Failed("buy paid out nothing".to_string()));
+    }
+
+    // sell: whatever the transfer delivers is what the pair can pay for
+    let (weth_reserve, token_reserve) = reserves().await?;
+    let in_pair = token_erc20.balanceOf(pair_address).call().await?;
+    if !succeeded(
+        token_erc20
+            .transfer(pair_address, bought)
+            .from(trader)
+            .send()
+            .await,
+    )
+    .await?
+    {
+        return Ok(Verdict::Failed("transfer to the pair reverted".to_string()));
+    }
+    let delivered = token_erc20
+        .balanceOf(pair_address)
+        .call()
+        .await?
+        .saturating_sub(in_pair);
+    let (Some(expected), Some(payable)) = (
+        get_amount_out(bought, token_reserve, weth_reserve, pool.fee),
+        get_amount_out(delivered, token_reserve, weth_reserve, pool.fee),
+    ) else {
+        return Ok(Verdict::Failed(
+            "sell delivered nothing to the pair".to_string(),
+        ));
+    };
+    let weth_held = weth_erc20.balanceOf(trader).call().await?;
+    let (out0, out1) = outs(payable, U256::ZERO);
+    if !succeeded(
+        pair.swap(out0, out1, trader, Bytes::new())
+            .from(trader)
+            .send()
+            .await,
+    )
+    .await?
+    {
+        return Ok(Verdict::Failed("sell reverted".to_string()));
+    }
+    let sold = weth_erc20
+        .balanceOf(trader)
+        .call()
+        .await?
+        .saturating_sub(weth_held);
+
+    Ok(Verdict::Passed {
+        buy_tax: shortfall(bought, promised),
+        sell_tax: shortfall(sold, expected),
+    })
+}
+
+/// Share of `promised` that didn't arrive
+fn shortfall(received: U256, promised: U256) -> f64 {
+    (1.0 - f64::from(received) / f64::from(promised)).max(0.0)
+}
+
+/// `Ok(false)` if the transaction reverted, whether the node refused it
+/// up front (gas estimation) or mined it failed. Any other error, such as
+/// a rate limit from the fork's upstream, is returned as one, so the
+/// token gets no verdict rather than a `Failed` one that would be cached.
+async fn succeeded(
+    sent: std::result::Result<PendingTransactionBuilder<Ethereum>, alloy::contract::Error>,
+) -> Result<bool> {
+    match sent {
+        Ok(pending) => Ok(pending.get_receipt().await?.status()),
+        Err(alloy::contract::Error::TransportError(e))
+            if e.as_error_resp()
+                .is_some_and(|r| is_revert(r.code, &r.message)) =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether a JSON-RPC error response reports an execution revert: code 3,
+/// or a "reverted" message as anvil words failed gas estimation
+fn is_revert(code: i64, message: &str) -> bool {
+    code == 3 || message.to_lowercase().contains("revert")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::pair;
+
+    fn weth_pair(id: &str, other: &str, weth_reserve: u128) -> Pool {
+        pair(id, other, DEFAULT_BASE_TOKENS[0], 1000, weth_reserve)
+    }
+
+    #[test]
+    fn lists_override_verdicts() {
+        let lists = TokenLists::from_json(r#"{ "allow": ["0xAB"], "deny": ["0xCD"] }"#).unwrap();
+        let verdicts = HashMap::from([
+            (
+                "0xab".to_string(),
+                Verdict::Failed("sell reverted".to_string()),
+            ),
+            (
+                "0xcd".to_string(),
+                Verdict::Passed {
+                    buy_tax: 0.0,
+                    sell_tax: 0.0,
+                },
+            ),
+            (
+                "0xtax".to_string(),
+                Verdict::Passed {
+                    buy_tax: 0.0,
+                    sell_tax: 0.1,
+                },
+            ),
+            (
+                "0xok".to_string(),
+                Verdict::Passed {
+                    buy_tax: 0.001,
+                    sell_tax: 0.0,
+                },
+            ),
+            (
+                "0xpot".to_string(),
+                Verdict::Failed("transfer to the pair reverted".to_string()),
+            ),
+        ]);
+        let mut classifier = TokenClassifier::new(lists, verdicts);
+
+        assert!(classifier.tradable("0xab"));
+        assert!(!classifier.tradable("0xcd"));
+        assert!(!classifier.tradable("0xtax"));
+        assert!(classifier.tradable("0xok"));
+        assert!(!classifier.tradable("0xpot"));
+        assert!(!classifier.tradable("0xunchecked"));
+
+        classifier.unchecked_policy = UncheckedPolicy::Allow;
+        assert!(classifier.tradable("0xunchecked"));
+        assert!(!classifier.tradable("0xpot"));
+    }
+
+    #[test]
+    fn checks_each_unclassified_token_once_through_its_deepest_pair() {
+        let lists = TokenLists::from_json(r#"{ "deny": ["0xbad"] }"#).unwrap();
+        let verdicts = HashMap::from([(
+            "0xknown".to_string(),
+            Verdict::Passed {
+                buy_tax: 0.0,
+                sell_tax: 0.0,
+            },
+        )]);
+        let classifier = TokenClassifier::new(lists, verdicts);
+        let pools = vec![
+            weth_pair("shallow", "0xnew", 10),
+            weth_pair("deep", "0xnew", 1_000),
+            weth_pair("denied", "0xbad", 1_000),
+            weth_pair("cached", "0xknown", 1_000),
+        ];
+
+        let unchecked = classifier.unchecked(&pools);
+        assert_eq!(unchecked.len(), 1);
+        assert_eq!(
+            (unchecked[0].0, unchecked[0].1.id.as_str()),
+            ("0xnew", "deep")
+        );
+    }
+
+    #[test]
+    fn only_reverts_count_as_failed_legs() {
+        assert!(is_revert(3, "execution reverted: TRANSFER_FAILED"));
+        assert!(is_revert(-32603, "EVM error: Reverted"));
+        assert!(!is_revert(429, "Too many requests"));
+        assert!(!is_revert(-32000, "header not found"));
+    }
+
+    #[test]
+    fn verdicts_round_trip_as_json() {
+        let verdict = Verdict::Passed {
+            buy_tax: 0.02,
+            sell_tax: 0.0,
+        };
+        let json = serde_json::to_string(&verdict).unwrap();
+        assert_eq!(serde_json::from_str::<Verdict>(&json).unwrap(), verdict);
+        assert!(TokenLists::from_json(r#"{ "allow": "0xab" }"#).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use alloy::{
+    providers::{DynProvider, Provider, ProviderBuilder, WsConnect},
+    rpc::client::ClientBuilder,
+    transports::layers::ThrottleLayer,
+};
+use eyre::{Result, bail, eyre};
+use serde::Deserialize;
+
+/// Config file read by `RpcConfig::load` when `ARB_RPC_CONFIG` isn't set
+pub const RPC_CONFIG_PATH: &str = "rpc.json";
+
+/// One RPC endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Endpoint {
+    pub url: String,
+    #[serde(default)]
+    pub requests_per_second: Option<u32>, // client-side throttle; None = unthrottled
+}
+
+/// -------------------------------
+/// RPC configuration
+/// -------------------------------
+/// Where to read the chain from. Comes from a JSON file
+///
+/// ```json
+/// { "chain_id": 1,
+///   "http": { "url": "https://...", "requests_per_second": 25 },
+///   "ws":   { "url": "wss://..." } }
+/// ```
+///
+/// and/or the environment (`ARB_HTTP_URL`, `ARB_WS_URL`, `ARB_CHAIN_ID`,
+/// `ARB_HTTP_RPS`, `ARB_WS_RPS`), which overrides the file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RpcConfig {
+    #[serde(default = "default_chain_id")]
+    pub chain_id: u64,
+    pub http: Endpoint,
+    #[serde(default)]
+    pub ws: Option<Endpoint>, // needed only for `--watch`
+}
+
+fn default_chain_id() -> u64 {
+    1
+}
+
+impl RpcConfig {
+    /// `ARB_RPC_CONFIG` (or `rpc.json` if present), then environment
+    /// overrides. Fails if no HTTP URL is configured anywhere.
+    pub fn load() -> Result<Self> {
+        let path = env::var("ARB_RPC_CONFIG").ok();
+        let file = match path.as_deref() {
+            Some(path) => Some(Self::from_file(path)?),
+            None if Path::new(RPC_CONFIG_PATH).exists() => Some(Self::from_file(RPC_CONFIG_PATH)?),
+            None => None,
+        };
+        Self::with_overrides(file, |key| env::var(key).ok())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("reading {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Apply `ARB_*` variables (looked up through `var`) over `base`
+    fn with_overrides(base: Option<Self>, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let rps = |key: &str| -> Result<Option<u32>> {
+            var(key)
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(|e| eyre!("{}: {}", key, e))
+        };
+
+        let http = match (var("ARB_HTTP_URL"), base.as_ref()) {
+            (Some(url), _) => Endpoint {
+                url,
+                requests_per_second: None,
+            },
+            (None, Some(base)) => base.http.clone(),
+            (None, None) => bail!(
+                "no RPC endpoint: set ARB_HTTP_URL or write {}",
+                RPC_CONFIG_PATH
+            ),
+        };
+        let ws = match var("ARB_WS_URL") {
+            Some(url) => Some(Endpoint {
+                url,
+                requests_per_second: None,
+            }),
+            None => base.as_ref().and_then(|b| b.ws.clone()),
+        };
+
+        let mut config = RpcConfig {
+            chain_id: base.as_ref().map_or(default_chain_id(), |b| b.chain_id),
+            http,
+            ws,
+        };
+        if let Some(chain_id) = var("ARB_CHAIN_ID") {
+            config.chain_id = chain_id.parse().map_err(|e| eyre!("ARB_CHAIN_ID: {}", e))?;
+        }
+        if let Some(limit) = rps("ARB_HTTP_RPS")? {
+            config.http.requests_per_second = Some(limit);
+        }
+        if let (Some(limit), Some(ws)) = (rps("ARB_WS_RPS")?, config.ws.as_mut()) {
+            ws.requests_per_second = Some(limit);
+        }
+        Ok(config)
+    }
+
+    /// Throttled HTTP provider, checked to be on `chain_id`
+    pub async fn http_provider(&self) -> Result<Arc<DynProvider>> {
+        let url = self.http.url.parse()?;
+        let client = match self.http.requests_per_second {
+            Some(rps) => ClientBuilder::default().layer(throttle(rps)?).http(url),
+            None => ClientBuilder::default().http(url),
+        };
+        let provider = ProviderBuilder::new().connect_client(client).erased();
+        self.check_chain(&provider).await?;
+        Ok(Arc::new(provider))
+    }
+
+    /// Pubsub provider for `live::watch_pools`
+    pub async fn ws_provider(&self) -> Result<Arc<DynProvider>> {
+        let Some(ws) = &self.ws else {
+            bail!("no websocket endpoint: set ARB_WS_URL or `ws` in the RPC config");
+        };
+        let connect = WsConnect::new(ws.url.clone());
+        let client = match ws.requests_per_second {
+            Some(rps) => {
+                ClientBuilder::default()
+                    .layer(throttle(rps)?)
+                    .ws(connect)
+                    .await?
+            }
+            None => ClientBuilder::default().ws(connect).await?,
+        };
+        let provider = ProviderBuilder::new().connect_client(client).erased();
+        self.check_chain(&provider).await?;
+        Ok(Arc::new(provider))
+    }
+
+    async fn check_chain(&self, provider: &DynProvider) -> Result<()> {
+        let chain_id = provider.get_chain_id().await?;
+        if chain_id != self.chain_id {
+            bail!(
+                "RPC endpoint is on chain {}, config expects {}",
+                chain_id,
+                self.chain_id
+            );
+        }
+        Ok(())
+    }
+}
+
+fn throttle(requests_per_second: u32) -> Result<ThrottleLayer> {
+    if requests_per_second == 0 {
+        bail!("requests_per_second must be positive");
+    }
+    Ok(ThrottleLayer::new(requests_per_second))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let map: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| map.get(key).cloned()
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let file: RpcConfig = serde_json::from_str(
+            r#"{ "http": { "url": "https://file", "requests_per_second": 10 },
+                 "ws": { "url": "wss://file" } }"#,
+        )
+        .unwrap();
+        assert_eq!(file.chain_id, 1);
+
+        let config = RpcConfig::with_overrides(
+            Some(file.clone()),
+            vars(&[("ARB_CHAIN_ID", "31337"), ("ARB_WS_RPS", "5")]),
+        )
+        .unwrap();
+        assert_eq!(config.chain_id, 31337);
+        assert_eq!(config.http, file.http);
+        assert_eq!(config.ws.unwrap().requests_per_second, Some(5));
+
+        let config = RpcConfig::with_overrides(
+            Some(file),
+            vars(&[("ARB_HTTP_URL", "http://localhost:8545")]),
+        )
+        .unwrap();
+        assert_eq!(config.http.url, "http://localhost:8545");
+        assert_eq!(config.http.requests_per_second, None);
+    }
+
+    #[test]
+    fn requires_an_http_endpoint() {
+        assert!(RpcConfig::with_overrides(None, vars(&[])).is_err());
+        assert!(
+            RpcConfig::with_overrides(
+                None,
+                vars(&[("ARB_HTTP_URL", "http://x"), ("ARB_HTTP_RPS", "fast")])
+            )
+            .is_err()
+        );
+    }
+}
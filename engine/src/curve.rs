@@ -0,0 +1,509 @@
+use std::sync::Arc;
+
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, U256, address},
+    providers::Provider,
+    sol,
+    sol_types::SolCall,
+};
+use dashmap::DashMap;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::datafetcher::{ADDR_BATCH, Call, IMulticall2, MULTICALL2, load_token};
+use crate::engine::Token;
+use crate::pool::{AmmPool, Protocol, whole_tokens};
+
+sol! {
+    #[sol(rpc)]
+    interface ICurvePool {
+        function coins(uint256 i) returns (address);
+        function balances(uint256 i) returns (uint256);
+        function A() returns (uint256);
+        function A_precise() returns (uint256);
+        function fee() returns (uint256);
+        function base_pool() returns (address);
+        function get_virtual_price() returns (uint256);
+    }
+}
+
+/// DAI/USDC/USDT 3pool
+pub const CURVE_3POOL: Address = address!("bEbc44782C7dB0a1A60Cb6fe97d0b483032FF1C7");
+
+/// Pools loaded by default, each with the fee order of the contract
+/// template it was deployed from
+pub const CURVE_POOLS: [(Address, FeeOrder); 1] = [(CURVE_3POOL, FeeOrder::CoinUnits)];
+
+const MAX_COINS: usize = 4;
+const FEE_DENOMINATOR: u64 = 10_000_000_000;
+/// A_precise() pools store A scaled by this; legacy pools only expose A()
+const A_PRECISION: u64 = 100;
+/// Newton iterations, as in the Vyper contracts
+const MAX_ITERATIONS: usize = 255;
+
+fn precision() -> U256 {
+    U256::from(10u64.pow(18))
+}
+
+/// Which units `get_dy` takes the fee in, fixed by the contract template
+/// rather than by anything the pool reports, so it is listed with each
+/// pool address. The two orders round apart by a wei or so, which is
+/// enough to fail an exact quote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeeOrder {
+    /// Convert dy out of xp first, then take the fee (StableSwap3Pool.vy
+    /// and the other early hand-deployed pools)
+    #[default]
+    CoinUnits,
+    /// Take the fee on dy in xp units, then convert (factory plain pools
+    /// and metapools)
+    XpUnits,
+}
+
+/// -------------------------------
+/// Pool model
+/// -------------------------------
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurvePool {
+    pub id: String,
+    pub tokens: Vec<Token>,
+    pub balances: Vec<U256>,
+    /// 10^(36 - decimals) per coin; for a metapool's base LP coin this is
+    /// the base pool's virtual price instead
+    pub rates: Vec<U256>,
+    pub amp: U256,                 // A · a_precision
+    pub a_precision: U256,         // 1 for legacy pools, 100 for A_precise pools
+    pub fee: U256,                 // over 1e10
+    pub base_pool: Option<String>, // set for metapools
+    #[serde(default)]
+    pub fee_order: FeeOrder,
+}
+
+impl CurvePool {
+    /// Fee as a fraction (0.0001 for 1 bp)
+    pub fn fee_fraction(&self) -> f64 {
+        f64::from(self.fee) / FEE_DENOMINATOR as f64
+    }
+
+    pub fn index_of(&self, token_id: &str) -> Option<usize> {
+        self.tokens.iter().position(|t| t.id == token_id)
+    }
+
+    /// Port of StableSwap `get_dy(i, j, dx)`, taking the fee in the units
+    /// `fee_order` says the pool's contract does.
+    pub fn get_dy(&self, i: usize, j: usize, dx: U256) -> Option<U256> {
+        let n = self.tokens.len();
+        if i == j || i >= n || j >= n || dx.is_zero() {
+            return None;
+        }
+
+        let xp = self.xp()?;
+        let x = xp[i].checked_add(dx.checked_mul(self.rates[i])?.checked_div(precision())?)?;
+        let y = self.get_y(i, j, x, &xp)?;
+
+        let dy = xp[j].checked_sub(y)?.checked_sub(U256::from(1))?;
+        match self.fee_order {
+            FeeOrder::CoinUnits => {
+                let dy = dy.checked_mul(precision())?.checked_div(self.rates[j])?;
+                dy.checked_sub(self.fee_on(dy)?)
+            }
+            FeeOrder::XpUnits => dy
+                .checked_sub(self.fee_on(dy)?)?
+                .checked_mul(precision())?
+                .checked_div(self.rates[j]),
+        }
+    }
+
+    fn fee_on(&self, dy: U256) -> Option<U256> {
+        self.fee
+            .checked_mul(dy)?
+            .checked_div(U256::from(FEE_DENOMINATOR))
+    }
+
+    /// Balances in 1e18 precision
+    fn xp(&self) -> Option<Vec<U256>> {
+        self.balances
+            .iter()
+            .zip(&self.rates)
+            .map(|(b, r)| b.checked_mul(*r)?.checked_div(precision()))
+            .collect()
+    }
+
+    /// StableSwap invariant D by Newton's method
+    fn get_d(&self, xp: &[U256]) -> Option<U256> {
+        let n = U256::from(xp.len());
+        let s = xp
+            .iter()
+            .try_fold(U256::ZERO, |acc, x| acc.checked_add(*x))?;
+        if s.is_zero() {
+            return Some(U256::ZERO);
+        }
+
+        let ann = self.amp.checked_mul(n)?;
+        let mut d = s;
+        for _ in 0..MAX_ITERATIONS {
+            let mut d_p = d;
+            for x in xp {
+                d_p = d_p.checked_mul(d)?.checked_div(x.checked_mul(n)?)?;
+            }
+            let d_prev = d;
+            let numerator = (ann.checked_mul(s)?.checked_div(self.a_precision)?)
+                .checked_add(d_p.checked_mul(n)?)?
+                .checked_mul(d)?;
+            let denominator = (ann
+                .checked_sub(self.a_precision)?
+                .checked_mul(d)?
+                .checked_div(self.a_precision)?)
+            .checked_add((n + U256::from(1)).checked_mul(d_p)?)?;
+            d = numerator.checked_div(denominator)?;
+
+            if d.abs_diff(d_prev) <= U256::from(1) {
+                return Some(d);
+            }
+        }
+        None
+    }
+
+    /// Balance of coin j after coin i is set to `x`, keeping D fixed
+    fn get_y(&self, i: usize, j: usize, x: U256, xp: &[U256]) -> Option<U256> {
+        let n = U256::from(xp.len());
+        let d = self.get_d(xp)?;
+        let ann = self.amp.checked_mul(n)?;
+
+        let mut c = d;
+        let mut s = U256::ZERO;
+        for (k, &xk) in xp.iter().enumerate() {
+            let xk = if k == i {
+                x
+            } else if k != j {
+                xk
+            } else {
+                continue;
+            };
+            s = s.checked_add(xk)?;
+            c = c.checked_mul(d)?.checked_div(xk.checked_mul(n)?)?;
+        }
+        c = c
+            .checked_mul(d)?
+            .checked_mul(self.a_precision)?
+            .checked_div(ann.checked_mul(n)?)?;
+        let b = s.checked_add(d.checked_mul(self.a_precision)?.checked_div(ann)?)?;
+
+        let mut y = d;
+        for _ in 0..MAX_ITERATIONS {
+            let y_prev = y;
+            let denominator = y
+                .checked_mul(U256::from(2))?
+                .checked_add(b)?
+                .checked_sub(d)?;
+            y = y.checked_mul(y)?.checked_add(c)?.checked_div(denominator)?;
+            if y.abs_diff(y_prev) <= U256::from(1) {
+                return Some(y);
+            }
+        }
+        None
+    }
+}
+
+impl AmmPool for CurvePool {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::Curve
+    }
+
+    fn tokens(&self) -> Vec<&Token> {
+        self.tokens.iter().collect()
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee_fraction()
+    }
+
+    fn holdings(&self) -> Option<Vec<f64>> {
+        Some(
+            self.balances
+                .iter()
+                .zip(&self.tokens)
+                .map(|(b, t)| whole_tokens(f64::from(*b), t))
+                .collect(),
+        )
+    }
+
+    /// Priced with a small `get_dy`: a millionth of the input balance is
+    /// small enough to sit at the marginal price, large enough to stay
+    /// clear of rounding
+    fn spot_rate(&self, token_in: &str, token_out: &str) -> Option<f64> {
+        let i = self.index_of(token_in)?;
+        let j = self.index_of(token_out)?;
+        let dx = self.balances[i] / U256::from(1_000_000);
+        let dy = self.get_dy(i, j, dx)?;
+        Some(f64::from(dy) / f64::from(dx))
+    }
+
+    fn quote(&self, amount_in: U256, token_in: &str, token_out: &str) -> Option<U256> {
+        self.get_dy(
+            self.index_of(token_in)?,
+            self.index_of(token_out)?,
+            amount_in,
+        )
+    }
+}
+
+/// -------------------------------
+/// On-chain loading (multicall)
+/// -------------------------------
+/// Load coins, balances, A, fee and coin decimals for every pool in
+/// `pools` (address, fee order), as of `block`. Metapools also get the
+/// base pool's virtual price as the rate of their LP coin.
+pub async fn fetch_curve_pools<P>(
+    provider: Arc<P>,
+    pools_to_load: &[(Address, FeeOrder)],
+    cache: Arc<DashMap<Address, Token>>,
+    block: BlockId,
+) -> Result<Vec<CurvePool>>
+where
+    P: Provider + 'static,
+{
+    let multicall = IMulticall2::new(MULTICALL2, provider.clone());
+    let mut pools = Vec::new();
+
+    // coins(i), balances(i) for i < MAX_COINS, then A, A_precise, fee, base_pool
+    let per_pool = 2 * MAX_COINS + 4;
+
+    for chunk in pools_to_load.chunks(ADDR_BATCH) {
+        let mut calls = Vec::with_capacity(chunk.len() * per_pool);
+        for &(pool, _) in chunk {
+            for i in 0..MAX_COINS {
+                calls.push(Call {
+                    target: pool,
+                    callData: ICurvePool::coinsCall { i: U256::from(i) }
+                        .abi_encode()
+                        .into(),
+                });
+            }
+            for i in 0..MAX_COINS {
+                calls.push(Call {
+                    target: pool,
+                    callData: ICurvePool::balancesCall { i: U256::from(i) }
+                        .abi_encode()
+                        .into(),
+                });
+            }
+            for data in [
+                ICurvePool::ACall.abi_encode(),
+                ICurvePool::A_preciseCall.abi_encode(),
+                ICurvePool::feeCall.abi_encode(),
+                ICurvePool::base_poolCall.abi_encode(),
+            ] {
+                calls.push(Call {
+                    target: pool,
+                    callData: data.into(),
+                });
+            }
+        }
+
+        // out-of-range coins() and missing A_precise()/base_pool() revert,
+        // so don't require every call to succeed
+        let res = multicall
+            .tryAggregate(false, calls)
+            .block(block)
+            .call()
+            .await?;
+
+        for (k, &(pool, fee_order)) in chunk.iter().enumerate() {
+            let r = &res[k * per_pool..(k + 1) * per_pool];
+            let ok = |idx: usize| r[idx].success && !r[idx].returnData.is_empty();
+
+            let mut coins = Vec::new();
+            let mut balances = Vec::new();
+            for i in 0..MAX_COINS {
+                if !ok(i) || !ok(MAX_COINS + i) {
+                    break;
+                }
+                coins.push(ICurvePool::coinsCall::abi_decode_returns(&r[i].returnData)?);
+                balances.push(ICurvePool::balancesCall::abi_decode_returns(
+                    &r[MAX_COINS + i].returnData,
+                )?);
+            }
+            let base = 2 * MAX_COINS;
+            if coins.len() < 2 || !ok(base + 2) {
+                eprintln!("Skipping Curve pool {:#x}: not a StableSwap pool", pool);
+                continue;
+            }
+
+            let (amp, a_precision) = if ok(base + 1) {
+                (
+                    ICurvePool::A_preciseCall::abi_decode_returns(&r[base + 1].returnData)?,
+                    U256::from(A_PRECISION),
+                )
+            } else if ok(base) {
+                (
+                    ICurvePool::ACall::abi_decode_returns(&r[base].returnData)?,
+                    U256::from(1),
+                )
+            } else {
+                eprintln!("Skipping Curve pool {:#x}: no A()", pool);
+                continue;
+            };
+            let fee = ICurvePool::feeCall::abi_decode_returns(&r[base + 2].returnData)?;
+            let base_pool = if ok(base + 3) {
+                Some(ICurvePool::base_poolCall::abi_decode_returns(
+                    &r[base + 3].returnData,
+                )?)
+                .filter(|a| *a != Address::ZERO)
+            } else {
+                None
+            };
+
+            let mut tokens = Vec::with_capacity(coins.len());
+            for &coin in &coins {
+                match load_token(coin, provider.clone(), &cache, block).await {
+                    Ok(t) => tokens.push(t),
+                    Err(e) => {
+                        eprintln!("Error loading coin {:#x} of {:#x}: {:?}", coin, pool, e);
+                        break;
+                    }
+                }
+            }
+            if tokens.len() != coins.len() {
+                continue;
+            }
+
+            let mut rates = Vec::with_capacity(tokens.len());
+            for t in &tokens {
+                let decimals = u32::from(t.decimals);
+                rates.push(U256::from(10).pow(U256::from(36u32.saturating_sub(decimals))));
+            }
+
+            // metapool: the last coin is the base pool's LP token, priced
+            // at the base pool's virtual price
+            if let Some(bp) = base_pool {
+                let vp = ICurvePool::new(bp, provider.clone())
+                    .get_virtual_price()
+                    .block(block)
+                    .call()
+                    .await?;
+                if let Some(last) = rates.last_mut() {
+                    *last = vp;
+                }
+            }
+
+            pools.push(CurvePool {
+                id: format!("{:#x}", pool),
+                tokens,
+                balances,
+                rates,
+                amp,
+                a_precision,
+                fee,
+                base_pool: base_pool.map(|a| format!("{:#x}", a)),
+                fee_order,
+            });
+        }
+    }
+
+    Ok(pools)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::token_with_decimals;
+
+    /// DAI/USDC/USDT-shaped pool with `units` of each coin
+    fn three_pool(units: [u64; 3], amp: u64, a_precision: u64) -> CurvePool {
+        let decimals = [18u8, 6, 6];
+        CurvePool {
+            id: "3pool".to_string(),
+            tokens: vec![
+                token_with_decimals("dai", 18),
+                token_with_decimals("usdc", 6),
+                token_with_decimals("usdt", 6),
+            ],
+            balances: units
+                .iter()
+                .zip(decimals)
+                .map(|(u, d)| U256::from(*u) * U256::from(10).pow(U256::from(d)))
+                .collect(),
+            rates: decimals
+                .iter()
+                .map(|d| U256::from(10).pow(U256::from(36 - d)))
+                .collect(),
+            amp: U256::from(amp * a_precision),
+            a_precision: U256::from(a_precision),
+            fee: U256::from(1_000_000), // 1 bp
+            base_pool: None,
+            fee_order: FeeOrder::CoinUnits,
+        }
+    }
+
+    #[test]
+    fn balanced_pool_d_is_sum() {
+        let p = three_pool([1_000_000; 3], 2000, 1);
+        let d = p.get_d(&p.xp().unwrap()).unwrap();
+        let sum = U256::from(3_000_000u64) * precision();
+        assert!(d.abs_diff(sum) <= U256::from(3));
+    }
+
+    #[test]
+    fn balanced_swap_is_near_par_less_fee() {
+        let p = three_pool([100_000_000; 3], 2000, 1);
+        // 1000 DAI -> USDC
+        let dy = p.get_dy(0, 1, U256::from(1000u64) * precision()).unwrap();
+        let usdc = f64::from(dy) / 1e6;
+        assert!(usdc < 1000.0 * (1.0 - 0.0001) && usdc > 999.8, "{usdc}");
+    }
+
+    #[test]
+    fn a_precision_does_not_change_quotes() {
+        let legacy = three_pool([40_000_000, 60_000_000, 55_000_000], 2000, 1);
+        let precise = three_pool([40_000_000, 60_000_000, 55_000_000], 2000, A_PRECISION);
+        let dx = U256::from(250_000u64) * U256::from(1_000_000u64);
+        let a = legacy.get_dy(1, 0, dx).unwrap();
+        let b = precise.get_dy(1, 0, dx).unwrap();
+        assert!(a.abs_diff(b) <= U256::from(10u64.pow(6)), "{a} vs {b}");
+    }
+
+    #[test]
+    fn matches_3pool_get_dy() {
+        // StableSwap3Pool.vy get_dy(1, 2, 250k USDC), worked in big ints
+        let mut p = three_pool([40_000_000, 60_000_000, 55_000_000], 2000, 1);
+        let dx = U256::from(250_000u64) * U256::from(1_000_000u64);
+        assert_eq!(p.get_dy(1, 2, dx), Some(U256::from(249_964_266_894u64)));
+
+        // the factory and metapool order rounds a wei lower here
+        p.fee_order = FeeOrder::XpUnits;
+        assert_eq!(p.get_dy(1, 2, dx), Some(U256::from(249_964_266_893u64)));
+    }
+
+    #[test]
+    fn one_sided_empty_pool_gives_no_edges() {
+        // every division that would hit the empty coin is checked
+        let p = three_pool([1_000_000, 0, 1_000_000], 2000, 1);
+        assert_eq!(p.get_dy(0, 2, U256::from(1000u64) * precision()), None);
+        assert!(p.edges().is_empty());
+    }
+
+    #[test]
+    fn scarce_coin_trades_at_premium() {
+        // little USDT in the pool: selling DAI for USDT pays less than par
+        let p = three_pool([100_000_000, 100_000_000, 10_000_000], 200, 1);
+        let edges = p.edges();
+        let dai_to_usdt = edges
+            .iter()
+            .find(|e| e.from == "dai" && e.to == "usdt")
+            .unwrap();
+        let usdt_to_dai = edges
+            .iter()
+            .find(|e| e.from == "usdt" && e.to == "dai")
+            .unwrap();
+        // whole tokens: 1 DAI ~ 1 USDT, whatever the decimals
+        assert!(dai_to_usdt.rate < 1.0 && dai_to_usdt.rate > 0.9);
+        assert!(usdt_to_dai.rate > 1.0);
+        assert_eq!(edges.len(), 6);
+    }
+}
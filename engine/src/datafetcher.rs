@@ -1,17 +1,31 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 //import time in seconds
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use alloy::{
-    dyn_abi::parser::Error, primitives::{Address, U256, address}, providers::{Provider, ProviderBuilder, WsConnect, bindings::IMulticall3::IMulticall3Calls}, signers::k256::elliptic_curve::pkcs8::der, sol, sol_types::SolCall
+    dyn_abi::parser::Error,
+    eips::BlockId,
+    primitives::{Address, U256, address},
+    providers::{Provider, bindings::IMulticall3::IMulticall3Calls},
+    signers::k256::elliptic_curve::pkcs8::der,
+    sol,
+    sol_types::{SolCall, SolValue},
 };
 use dashmap::DashMap;
 use eyre::Result;
 // use eyre::Result;
 use tokio::sync::Semaphore;
 
-use crate::engine::{Pool, Token};
+use crate::balancer::fetch_balancer_pools;
+use crate::curve::{FeeOrder, fetch_curve_pools};
 use crate::engine;
+use crate::engine::{DecimalsStatus, MAX_PLAUSIBLE_DECIMALS, Pool, ReserveUnits, Token};
+use crate::quote::FEE_DENOMINATOR;
+use crate::registry::Registry;
+use crate::snapshot::MarketSnapshot;
+use crate::uniswap_v2::{V2_FACTORIES, V2Factory, V2Fee};
+use crate::uniswap_v3::{V3Pool, discover_v3_pools, fetch_v3_pools};
 
 sol! {
     #[sol(rpc)]
@@ -25,7 +39,9 @@ sol! {
         function token0() returns (address);
         function token1() returns (address);
         function getReserves()
-            returns (uint112 reserve0, uint112 reserve1, uint32);
+            returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+        // Biswap-style forks: per-pair fee
+        function swapFee() returns (uint32);
     }
     #[sol(rpc)]
     interface IERC20Metadata {
@@ -37,50 +53,440 @@ sol! {
         address target;
         bytes callData;
     }
+    struct CallResult {
+        bool success;
+        bytes returnData;
+    }
+
+    struct Call3 {
+        address target;
+        bool allowFailure;
+        bytes callData;
+    }
+    struct Call3Result {
+        bool success;
+        bytes returnData;
+    }
+
+    #[sol(rpc)]
+    interface IMulticall3 {
+        function aggregate3(Call3[] calls)
+            returns (Call3Result[] returnData);
+    }
 
     #[sol(rpc)]
     interface IMulticall2 {
         function aggregate(Call[] calls)
             returns (uint256, bytes[] returnData);
+        function tryAggregate(bool requireSuccess, Call[] calls)
+            returns (CallResult[] returnData);
     }
 }
 
-const UNISWAP_V2_FACTORY: Address = address!("5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f");
-const MULTICALL2: Address = address!("0x5ba1e12693dc8f9c48aad8770482f4739beed696");
-const ADDR_BATCH: usize = 30;
+pub(crate) const MULTICALL2: Address = address!("0x5ba1e12693dc8f9c48aad8770482f4739beed696");
+/// Same address on every chain it's deployed to
+pub(crate) const MULTICALL3: Address = address!("0xcA11bde05977b3631167028862bE2a173976CA11");
+pub(crate) const ADDR_BATCH: usize = 30;
 const MAX_CONCURRENCY: usize = 3;
+/// `allPairs` lookups per multicall: 32 bytes of return data and a few
+/// thousand gas each, far inside eth_call limits
+const PAIRS_BATCH: usize = 500;
+/// Tokens per metadata multicall (name, symbol, decimals each)
+const TOKEN_BATCH: usize = 100;
+const MAX_RETRIES: u32 = 4;
+const RETRY_BACKOFF_MS: u64 = 250;
 
 //#[tokio::main]
-pub async fn data_fetcher() -> Result<Vec<Pool>> {
-    let rpc_url = "https://eth-mainnet.g.alchemy.com/v2/KlDOgzk8zc0vdF4cQRXs3".parse()?;
-    let provider = ProviderBuilder::new().connect_http(rpc_url);
+/// V2 pairs from every known factory at the latest block, on any provider
+/// (see `config::RpcConfig` for a configured one). Reserves recorded in
+/// `registry` within `max_reserve_age` blocks are reused; pass 0 for
+/// anything that feeds the engine, so every pair is read at one block.
+pub async fn data_fetcher<P>(
+    provider: Arc<P>,
+    registry: &Registry,
+    max_reserve_age: u64,
+) -> Result<Vec<Pool>>
+where
+    P: Provider + 'static,
+{
+    let block_number = provider.get_block_number().await?;
+    let token_cache: Arc<DashMap<Address, Token>> = Arc::new(DashMap::new());
+    let fetched = v2_data_fetcher(
+        provider,
+        &V2_FACTORIES,
+        block_number,
+        token_cache,
+        registry,
+        max_reserve_age,
+        true,
+    )
+    .await?;
+    if !fetched.skipped.is_empty() {
+        println!(
+            "skipped {} pairs that failed to load",
+            fetched.skipped.len()
+        );
+    }
+    Ok(fetched.pools)
+}
+
+/// -------------------------------
+/// Pool sources
+/// -------------------------------
+/// Where the V2 pairs of a snapshot come from: factories on-chain
+/// (`OnChainSource`), a subgraph (`subgraph::SubgraphSource`), or either
+/// re-read on-chain (`Verified`).
+pub trait PoolSource {
+    /// Short label for logs
+    fn name(&self) -> &str;
+
+    /// Pairs as of `block_number` where the source can pin a block; token
+    /// metadata it resolves goes through `token_cache`
+    fn fetch_pools(
+        &self,
+        block_number: u64,
+        token_cache: Arc<DashMap<Address, Token>>,
+    ) -> impl Future<Output = Result<PoolFetch>>;
+}
+
+/// Pairs listed from V2 factories and read at the block, with listings
+/// and reserves reused from `registry` (see `v2_data_fetcher`). Without
+/// `discover`, only the pairs `registry` tracks are read.
+pub struct OnChainSource<'a, P> {
+    pub provider: Arc<P>,
+    pub factories: &'a [V2Factory],
+    pub registry: &'a Registry,
+    pub max_reserve_age: u64,
+    pub discover: bool,
+}
 
-    let provider = Arc::new(provider);
+impl<P> PoolSource for OnChainSource<'_, P>
+where
+    P: Provider + 'static,
+{
+    fn name(&self) -> &str {
+        "on-chain"
+    }
+
+    async fn fetch_pools(
+        &self,
+        block_number: u64,
+        token_cache: Arc<DashMap<Address, Token>>,
+    ) -> Result<PoolFetch> {
+        v2_data_fetcher(
+            self.provider.clone(),
+            self.factories,
+            block_number,
+            token_cache,
+            self.registry,
+            self.max_reserve_age,
+            self.discover,
+        )
+        .await
+    }
+}
+
+/// Pairs from another source, re-read on-chain at the block: the list
+/// comes from `source`, tokens and reserves from the chain, fees from
+/// the matching entry of `factories`
+pub struct Verified<S, P> {
+    pub source: S,
+    pub provider: Arc<P>,
+    pub factories: Vec<V2Factory>,
+}
+
+impl<S, P> PoolSource for Verified<S, P>
+where
+    S: PoolSource,
+    P: Provider + 'static,
+{
+    fn name(&self) -> &str {
+        self.source.name()
+    }
+
+    async fn fetch_pools(
+        &self,
+        block_number: u64,
+        token_cache: Arc<DashMap<Address, Token>>,
+    ) -> Result<PoolFetch> {
+        let listed = self
+            .source
+            .fetch_pools(block_number, token_cache.clone())
+            .await?;
+        let mut fetched = verify_pools(
+            self.provider.clone(),
+            &listed.pools,
+            &self.factories,
+            block_number,
+            token_cache,
+        )
+        .await?;
+        println!(
+            "{}: {} of {} pairs verified on-chain",
+            self.name(),
+            fetched.pools.len(),
+            listed.pools.len()
+        );
+        fetched.skipped.extend(listed.skipped);
+        Ok(fetched)
+    }
+}
+
+/// Re-read `pools` on-chain at `block_number`, grouped by the factory of
+/// `factories` named in `Pool.dex`, so only on-chain tokens, fees and raw
+/// reserves are kept. Pairs of an unknown DEX are skipped.
+pub async fn verify_pools<P>(
+    provider: Arc<P>,
+    pools: &[Pool],
+    factories: &[V2Factory],
+    block_number: u64,
+    token_cache: Arc<DashMap<Address, Token>>,
+) -> Result<PoolFetch>
+where
+    P: Provider + 'static,
+{
+    let mut fetched = PoolFetch::default();
+    let mut by_dex: Vec<(V2Factory, Vec<Address>)> = Vec::new();
+    for p in pools {
+        let Ok(pool) = p.id.parse::<Address>() else {
+            eprintln!("Skipping pair {}: not an address", p.id);
+            continue;
+        };
+        let Some(dex) = factories.iter().find(|f| f.name == p.dex) else {
+            fetched.skipped.push(SkippedPool {
+                pool,
+                reason: SkipReason::UnknownDex(p.dex.clone()),
+            });
+            continue;
+        };
+        match by_dex.iter_mut().find(|(d, _)| d.name == dex.name) {
+            Some((_, addrs)) => addrs.push(pool),
+            None => by_dex.push((*dex, vec![pool])),
+        }
+    }
+
+    for (dex, addrs) in by_dex {
+        let states = fetch_pool_states(
+            provider.clone(),
+            &addrs,
+            token_cache.clone(),
+            dex,
+            block_number,
+        )
+        .await?;
+        fetched.pools.extend(states.pools);
+        fetched.skipped.extend(states.skipped);
+    }
+    Ok(fetched)
+}
 
-    let factory = IUniswapV2Factory::new(UNISWAP_V2_FACTORY, provider.clone());
+/// Fetch one consistent view of the market: pairs from `v2_source` and
+/// the given Curve and Balancer pools, every call pinned to the same
+/// block. Token metadata comes from `registry` where already known and is
+/// recorded there. V3 pools are added once the pairs are screened (see
+/// `add_v3_pools`).
+pub async fn fetch_snapshot<P, S>(
+    provider: Arc<P>,
+    v2_source: &S,
+    curve_pools: &[(Address, FeeOrder)],
+    balancer_addrs: &[Address],
+    registry: &Registry,
+) -> Result<MarketSnapshot>
+where
+    P: Provider + 'static,
+    S: PoolSource,
+{
+    let block_number = provider.get_block_number().await?;
+    let block = BlockId::number(block_number);
+    let timestamp = provider
+        .get_block_by_number(block_number.into())
+        .await?
+        .map(|b| b.header.timestamp)
+        .ok_or_else(|| eyre::eyre!("block {} not found", block_number))?;
+    println!("pinning snapshot to block {}", block_number);
+    // shared across protocols: they list the same tokens
     let token_cache: Arc<DashMap<Address, Token>> = Arc::new(DashMap::new());
 
-    let pairs_len: U256 = factory.allPairsLength().call().await?;
-    println!("Total pairs: {}", pairs_len);
-    let mut pools: Vec<Pool> = Vec::new();
+    registry.load_tokens(&token_cache)?;
 
-    let max = pairs_len.min(U256::from(20));
+    let v2 = v2_source
+        .fetch_pools(block_number, token_cache.clone())
+        .await?;
+    println!("{}: {} V2 pairs", v2_source.name(), v2.pools.len());
+    let curve_pools =
+        fetch_curve_pools(provider.clone(), curve_pools, token_cache.clone(), block).await?;
+    let balancer_pools =
+        fetch_balancer_pools(provider, balancer_addrs, token_cache.clone(), block).await?;
+    registry.save_tokens(&token_cache)?;
 
-    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENCY));
-    //let mut tasks = Vec::new();
-    println!("initiating fetching all pool addresses");
+    Ok(MarketSnapshot {
+        block_number,
+        timestamp,
+        v2_pools: v2.pools,
+        v3_pools: Vec::new(),
+        curve_pools,
+        balancer_pools,
+        skipped: v2.skipped,
+    })
+}
+
+/// Fetch pairs from each V2-compatible factory as of `block_number`,
+/// every `Pool` tagged with the DEX (and fee) it came from. Pairs that
+/// fail to load are listed in `PoolFetch.skipped` instead of failing the
+/// fetch.
+///
+/// Only pairs created since the last run are listed, and only reserves
+/// read more than `max_reserve_age` blocks ago are re-read; the rest come
+/// from `registry`, which is brought up to date. Without `discover`
+/// nothing is listed and only the pairs `registry` tracks are read.
+pub async fn v2_data_fetcher<P>(
+    provider: Arc<P>,
+    factories: &[V2Factory],
+    block_number: u64,
+    token_cache: Arc<DashMap<Address, Token>>,
+    registry: &Registry,
+    max_reserve_age: u64,
+    discover: bool,
+) -> Result<PoolFetch>
+where
+    P: Provider + 'static,
+{
+    let block = BlockId::number(block_number);
+    let mut fetched = PoolFetch::default();
+    registry.load_tokens(&token_cache)?;
 
-    let pool_addresses = fetch_pools(provider.clone(), UNISWAP_V2_FACTORY, 50).await?;
-    println!("all pool addresses fetched");
-    let pools=fetch_pool_states(provider, &pool_addresses, token_cache).await.unwrap();
+    for dex in factories {
+        let tracked = if discover {
+            let factory = IUniswapV2Factory::new(dex.factory, provider.clone());
+            let pairs_len: U256 = factory.allPairsLength().block(block).call().await?;
+            let count = pairs_len.to::<usize>();
+            let listed = registry.pairs_listed(dex)?.min(count);
+            println!(
+                "{}: total pairs: {} ({} new)",
+                dex.name,
+                pairs_len,
+                count - listed
+            );
 
-    Ok(pools)
+            // each batch is recorded as it arrives, so an interrupted
+            // listing resumes where it stopped
+            fetch_pools(
+                provider.clone(),
+                dex.factory,
+                block,
+                listed,
+                count,
+                |first, pairs| {
+                    registry.record_pairs(dex, first, pairs)?;
+                    let done = first + pairs.len();
+                    if done % (PAIRS_BATCH * 20) == 0 || done == count {
+                        println!("{}: {}/{} pair addresses", dex.name, done, count);
+                    }
+                    Ok(())
+                },
+            )
+            .await?;
+            None
+        } else {
+            let tracked = registry.tracked_pairs(dex)?;
+            println!("{}: {} tracked pairs", dex.name, tracked.len());
+            Some(tracked)
+        };
+        let wanted = |pair: &Address| tracked.as_ref().is_none_or(|t| t.contains(pair));
+
+        let stale: Vec<Address> = registry
+            .stale_pairs(dex, block_number, max_reserve_age)?
+            .into_iter()
+            .filter(|pair| wanted(pair))
+            .collect();
+        println!("{}: refreshing {} stale reserves", dex.name, stale.len());
+        let states = fetch_pool_states(
+            provider.clone(),
+            &stale,
+            token_cache.clone(),
+            *dex,
+            block_number,
+        )
+        .await?;
+        registry.save_tokens(&token_cache)?;
+        registry.save_pools(&states.pools)?;
+
+        fetched
+            .pools
+            .extend(registry.load_pools(dex)?.into_iter().filter(|p| {
+                p.block_number
+                    .is_some_and(|b| b + max_reserve_age >= block_number)
+                    && p.id.parse().is_ok_and(|pair| wanted(&pair))
+            }));
+        fetched.skipped.extend(states.skipped);
+    }
+
+    Ok(fetched)
 }
-// helper to load Token from an on-chain ERC20
-async fn load_token<P>(
+
+/// Add the Uniswap V3 pools that trade the same pairs as the snapshot's
+/// V2 pools, across every fee tier and at the snapshot's block, so
+/// cross-protocol cycles show up in the graph. Run it after screening:
+/// every surviving pair costs four `getPool` lookups. Returns how many
+/// were added.
+pub async fn add_v3_pools<P>(
+    provider: Arc<P>,
+    snapshot: &mut MarketSnapshot,
+    registry: &Registry,
+) -> Result<usize>
+where
+    P: Provider + 'static,
+{
+    let token_cache: Arc<DashMap<Address, Token>> = Arc::new(DashMap::new());
+    registry.load_tokens(&token_cache)?;
+    let block = BlockId::number(snapshot.block_number);
+    let v3_pools =
+        v3_data_fetcher(provider, &snapshot.v2_pools, block, token_cache.clone()).await?;
+    registry.save_tokens(&token_cache)?;
+
+    let added = v3_pools.len();
+    snapshot.v3_pools.extend(v3_pools);
+    Ok(added)
+}
+
+/// Fetch the Uniswap V3 pools that trade the same pairs as `v2_pools`,
+/// across every fee tier.
+async fn v3_data_fetcher<P>(
+    provider: Arc<P>,
+    v2_pools: &[Pool],
+    block: BlockId,
+    token_cache: Arc<DashMap<Address, Token>>,
+) -> Result<Vec<V3Pool>>
+where
+    P: Provider + 'static,
+{
+    let mut pairs: Vec<(Address, Address)> = Vec::new();
+    let mut seen = HashSet::new();
+    for p in v2_pools {
+        let pair = (
+            p.token0.id.parse::<Address>()?,
+            p.token1.id.parse::<Address>()?,
+        );
+        if seen.insert(pair) {
+            pairs.push(pair);
+        }
+    }
+
+    let v3_addrs = discover_v3_pools(provider.clone(), &pairs, block).await?;
+    println!(
+        "found {} V3 pools for {} pairs",
+        v3_addrs.len(),
+        pairs.len()
+    );
+    fetch_v3_pools(provider, &v3_addrs, token_cache, block).await
+}
+
+// helper to load Token from an on-chain ERC20, as of `block`
+pub(crate) async fn load_token<P>(
     addr: Address,
     provider: Arc<P>,
     cache: &DashMap<Address, Token>,
+    block: BlockId,
 ) -> Result<Token>
 where
     P: Provider + 'static,
@@ -89,20 +495,101 @@ where
         return Ok(token.clone());
     }
 
-    let erc20 = IERC20Metadata::new(addr, provider);
-    let name: String = erc20.name().call().await?;
-    let symbol: String = erc20.symbol().call().await?;
-    let decimals_u8: u8 = erc20.decimals().call().await?;
-    println!("allfetched");
-    let token = Token {
-        id: format!("{:#x}", addr),
-        name,
-        symbol,
-        decimals: decimals_u8.to_string(),
+    load_tokens(provider, &[addr], cache, block).await?;
+    cache
+        .get(&addr)
+        .map(|t| t.clone())
+        .ok_or_else(|| eyre::eyre!("metadata unavailable"))
+}
+
+/// Resolve ERC20 metadata for every address not yet in `cache` as of `block`,
+/// `TOKEN_BATCH` tokens (three calls each) per multicall, and fill the cache
+/// from the results.
+///
+/// Non-standard tokens still load: see `decode_text` and `decode_decimals`.
+pub(crate) async fn load_tokens<P>(
+    provider: Arc<P>,
+    addrs: &[Address],
+    cache: &DashMap<Address, Token>,
+    block: BlockId,
+) -> Result<()>
+where
+    P: Provider + 'static,
+{
+    let mut unseen: Vec<Address> = addrs
+        .iter()
+        .copied()
+        .filter(|a| !cache.contains_key(a))
+        .collect();
+    unseen.sort();
+    unseen.dedup();
+
+    let multicall = IMulticall3::new(MULTICALL3, provider);
+    for chunk in unseen.chunks(TOKEN_BATCH) {
+        let mut calls = Vec::with_capacity(chunk.len() * 3);
+        for &token in chunk {
+            for data in [
+                IERC20Metadata::nameCall.abi_encode(),
+                IERC20Metadata::symbolCall.abi_encode(),
+                IERC20Metadata::decimalsCall.abi_encode(),
+            ] {
+                calls.push(Call3 {
+                    target: token,
+                    allowFailure: true,
+                    callData: data.into(),
+                });
+            }
+        }
+        let res = multicall.aggregate3(calls).block(block).call().await?;
+
+        for (k, &addr) in chunk.iter().enumerate() {
+            let r = &res[k * 3..k * 3 + 3];
+            let (decimals, decimals_status) = decode_decimals(&r[2]);
+            cache.insert(
+                addr,
+                Token {
+                    id: format!("{:#x}", addr),
+                    name: decode_text(&r[0]),
+                    symbol: decode_text(&r[1]),
+                    decimals,
+                    decimals_status,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// ERC20 `name()`/`symbol()`: an ABI string, else a `bytes32` (MKR, SAI),
+/// else empty.
+fn decode_text(r: &Call3Result) -> String {
+    if !r.success {
+        return String::new();
+    }
+    if let Ok(text) = String::abi_decode(&r.returnData) {
+        return text;
+    }
+    if r.returnData.len() == 32 {
+        let end = r.returnData.iter().position(|&b| b == 0).unwrap_or(32);
+        return String::from_utf8_lossy(&r.returnData[..end]).into_owned();
+    }
+    String::new()
+}
+
+/// ERC20 `decimals()`, read as a full word since some tokens return a
+/// uint256. Missing decimals are assumed 18; both that and out-of-range
+/// values (saturated to a u8) are flagged rather than dropping the token.
+fn decode_decimals(r: &Call3Result) -> (u8, DecimalsStatus) {
+    if !r.success || r.returnData.len() < 32 {
+        return (18, DecimalsStatus::Missing);
+    }
+    let raw = U256::from_be_slice(&r.returnData[..32]);
+    let status = if raw > U256::from(MAX_PLAUSIBLE_DECIMALS) {
+        DecimalsStatus::Implausible
+    } else {
+        DecimalsStatus::Reported
     };
-    cache.insert(addr, token.clone());
-    println!("inserted token");
-    Ok(token)
+    (u8::try_from(raw).unwrap_or(u8::MAX), status)
 }
 //another helper function to build the multicall
 fn build_all_pairs_calls(start: U256, count: usize, factory: Address) -> Vec<Call> {
@@ -115,40 +602,160 @@ fn build_all_pairs_calls(start: U256, count: usize, factory: Address) -> Vec<Cal
         })
         .collect()
 }
-//helper fucntion to fetch all pools via multicall
-async fn fetch_pools<p>(provider: Arc<p>, factory: Address, count: usize) -> Result<Vec<Address>>
+/// Fetch a V2 factory's pair addresses via multicall.
+///
+/// Lists `allPairs(i)` for `i` in `start..end` as of `block`, `PAIRS_BATCH` per multicall,
+/// so a run can resume from the last index it reached. Failed batches are
+/// retried with backoff before giving up; `on_batch(first, pairs)` is called
+/// with every batch as it arrives, so it can be recorded before the next
+/// one is fetched, and an error from it stops the listing.
+pub async fn fetch_pools<P>(
+    provider: Arc<P>,
+    factory: Address,
+    block: BlockId,
+    start: usize,
+    end: usize,
+    mut on_batch: impl FnMut(usize, &[Address]) -> Result<()>,
+) -> Result<Vec<Address>>
 where
-    p: Provider + 'static,
+    P: Provider + 'static,
 {
-    let mut all_pairs = Vec::new();
-    let mut start = U256::ZERO;
-    while start < count {
-        let batch_size = ADDR_BATCH.min(count - start.to::<usize>());
-        let calls = build_all_pairs_calls(start, count, factory);
-        let multicall = IMulticall2::new(MULTICALL2, provider.clone());
-        let aggregate_result = multicall.aggregate(calls).call().await?;
-        let returndata = aggregate_result.returnData;
+    let multicall = IMulticall2::new(MULTICALL2, provider.clone());
+    let mut all_pairs = Vec::with_capacity(end.saturating_sub(start));
+    let mut next = start;
+    while next < end {
+        let batch_size = PAIRS_BATCH.min(end - next);
+        let calls = build_all_pairs_calls(U256::from(next), batch_size, factory);
+
+        let mut attempt = 0;
+        let returndata = loop {
+            match multicall.aggregate(calls.clone()).block(block).call().await {
+                Ok(res) => break res.returnData,
+                Err(e) if attempt < MAX_RETRIES => {
+                    attempt += 1;
+                    eprintln!(
+                        "allPairs batch at {} failed (attempt {}): {:?}",
+                        next, attempt, e
+                    );
+                    tokio::time::sleep(Duration::from_millis(RETRY_BACKOFF_MS << attempt)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let first = all_pairs.len();
         for raw in returndata {
             let bytes: &[u8] = &raw;
             let pair_addr: Address = IUniswapV2Factory::allPairsCall::abi_decode_returns(bytes)?;
-            // println!("{:?}", pair_addr);
             all_pairs.push(pair_addr);
         }
-        start += U256::from(batch_size);
+        on_batch(next, &all_pairs[first..])?;
+        next += batch_size;
     }
     Ok(all_pairs)
 }
 
+/// Why a pair was left out of a fetch
+#[derive(Debug, Clone)]
+pub enum SkipReason {
+    /// A sub-call reverted (broken token, self-destructed pair, ...)
+    Reverted(&'static str),
+    /// A sub-call returned data that doesn't decode
+    BadReturn(&'static str, String),
+    /// ERC20 metadata of one of the pair's tokens couldn't be loaded
+    Token(Address, String),
+    /// Listed by a source under a DEX with no known factory
+    UnknownDex(String),
+    /// The batch's RPC call kept failing after retries
+    Rpc(String),
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Reverted(call) => write!(f, "{call}() reverted"),
+            SkipReason::BadReturn(call, e) => write!(f, "{call}() returned bad data: {e}"),
+            SkipReason::Token(addr, e) => write!(f, "token {addr:#x}: {e}"),
+            SkipReason::UnknownDex(dex) => write!(f, "unknown DEX {dex:?}"),
+            SkipReason::Rpc(e) => write!(f, "RPC failed: {e}"),
+        }
+    }
+}
+
+/// A pair that was skipped, and why
+#[derive(Debug, Clone)]
+pub struct SkippedPool {
+    pub pool: Address,
+    pub reason: SkipReason,
+}
+
+/// Pools that loaded, plus the ones that didn't
+#[derive(Debug, Clone, Default)]
+pub struct PoolFetch {
+    pub pools: Vec<Pool>,
+    pub skipped: Vec<SkippedPool>,
+}
+
+/// Decode one `aggregate3` sub-result, or say why it can't be used.
+fn decode_call<C: SolCall>(
+    r: &Call3Result,
+    name: &'static str,
+) -> std::result::Result<C::Return, SkipReason> {
+    if !r.success {
+        return Err(SkipReason::Reverted(name));
+    }
+    C::abi_decode_returns(&r.returnData).map_err(|e| SkipReason::BadReturn(name, e.to_string()))
+}
+
+/// A pair's fee in hundredths of a bip: the factory's fixed fee, or its
+/// `swapFee()` result (`swap_fee`) scaled from the fork's denominator,
+/// with the fallback if that reverted or is out of range
+fn pair_fee(fee: V2Fee, swap_fee: Option<&Call3Result>) -> u32 {
+    match fee {
+        V2Fee::Fixed(fee) => fee,
+        V2Fee::PairSwapFee {
+            denominator,
+            fallback,
+        } => swap_fee
+            .and_then(|r| decode_call::<IUniswapV2Pair::swapFeeCall>(r, "swapFee").ok())
+            .and_then(|f| {
+                u32::try_from(
+                    u64::from(f) * u64::from(FEE_DENOMINATOR) / u64::from(denominator.max(1)),
+                )
+                .ok()
+            })
+            .filter(|&f| f < FEE_DENOMINATOR)
+            .unwrap_or(fallback),
+    }
+}
+
 //helper to fetch batch pool state fetcher
-async fn fetch_pool_states<p>(provider: Arc<p>, pool_addrs: &Vec<Address>, cache: Arc<DashMap<Address, Token>>) -> Result<Vec<Pool>>
+//
+// every sub-call may fail on its own (Multicall3 `allowFailure`), so one
+// broken pair only costs that pair, not its whole batch; a batch whose
+// multicall or token lookup keeps failing only costs its own pairs
+// (`SkipReason::Rpc`), not the fetch. Every batch reads the same block,
+// so pools from different batches are consistent
+async fn fetch_pool_states<P>(
+    provider: Arc<P>,
+    pool_addrs: &[Address],
+    cache: Arc<DashMap<Address, Token>>,
+    dex: V2Factory,
+    block_number: u64,
+) -> Result<PoolFetch>
 where
-    p: Provider + Send+Sync+'static,
+    P: Provider + Send + Sync + 'static,
 {
-    let mut pool_states:Vec<Pool>=Vec::new();
-    let semaphore=Arc::new(Semaphore::new(MAX_CONCURRENCY));
-    let multicall = IMulticall2::new(MULTICALL2, provider.clone());
-    let mut handles:Vec<tokio::task::JoinHandle<std::result::Result<_, eyre::Report>>>=Vec::new();
-    for chunk in pool_addrs.chunks(ADDR_BATCH){
+    let mut fetched = PoolFetch::default();
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENCY));
+    let multicall = IMulticall3::new(MULTICALL3, provider.clone());
+    let mut handles: Vec<tokio::task::JoinHandle<PoolFetch>> = Vec::new();
+    // token0, token1, getReserves (+ swapFee where the fork exposes it)
+    let per_pool = if matches!(dex.fee, V2Fee::PairSwapFee { .. }) {
+        4
+    } else {
+        3
+    };
+    for chunk in pool_addrs.chunks(ADDR_BATCH) {
         let permit = semaphore.clone().acquire_owned().await?;
         let multicall = multicall.clone();
         let chunk = chunk.to_vec();
@@ -156,114 +763,185 @@ where
         let cache_clone = cache.clone();
         handles.push(tokio::spawn(async move {
             let _permit = permit;
-            let mut calls = Vec::new();
+            let mut calls = Vec::with_capacity(chunk.len() * per_pool);
             for &pool in &chunk {
-                calls.push(Call {
-                    target: pool,
-                    callData: IUniswapV2Pair::token0Call.abi_encode().into(),
-                });
-                calls.push(Call {
-                    target: pool,
-                    callData: IUniswapV2Pair::token1Call.abi_encode().into(),
-                });
-                calls.push(Call {
-                    target: pool,
-                    callData: IUniswapV2Pair::getReservesCall.abi_encode().into(),
-                });
+                let mut encoded = vec![
+                    IUniswapV2Pair::token0Call.abi_encode(),
+                    IUniswapV2Pair::token1Call.abi_encode(),
+                    IUniswapV2Pair::getReservesCall.abi_encode(),
+                ];
+                if per_pool == 4 {
+                    encoded.push(IUniswapV2Pair::swapFeeCall.abi_encode());
+                }
+                for data in encoded {
+                    calls.push(Call3 {
+                        target: pool,
+                        allowFailure: true,
+                        callData: data.into(),
+                    });
+                }
             }
-            let res = match multicall.aggregate(calls).call().await {
-                Ok(result) => result,
-                Err(e) => {
-                    eprintln!("Error decoding multicall: {:?}", e);
-                    return Err(e.into());
+            let mut local = PoolFetch::default();
+            let skip_batch = |local: &mut PoolFetch, reason: String| {
+                eprintln!("Skipping {} pairs: {}", chunk.len(), reason);
+                local.skipped.extend(chunk.iter().map(|&pool| SkippedPool {
+                    pool,
+                    reason: SkipReason::Rpc(reason.clone()),
+                }));
+            };
+            let mut attempt = 0;
+            let res = loop {
+                match multicall
+                    .aggregate3(calls.clone())
+                    .block(BlockId::number(block_number))
+                    .call()
+                    .await
+                {
+                    Ok(result) => break result,
+                    Err(e) if attempt < MAX_RETRIES => {
+                        attempt += 1;
+                        eprintln!(
+                            "pair batch at {:#x} failed (attempt {}): {:?}",
+                            chunk[0], attempt, e
+                        );
+                        tokio::time::sleep(Duration::from_millis(RETRY_BACKOFF_MS << attempt))
+                            .await;
+                    }
+                    Err(e) => {
+                        skip_batch(&mut local, e.to_string());
+                        return local;
+                    }
                 }
             };
-            let mut local = Vec::with_capacity(chunk.len());
-            let mut i = 0;
+            if res.len() != calls.len() {
+                skip_batch(
+                    &mut local,
+                    format!("{} results for {} calls", res.len(), calls.len()),
+                );
+                return local;
+            }
+            let mut decoded_pairs = Vec::with_capacity(chunk.len());
+            let mut token_addrs = Vec::with_capacity(chunk.len() * 2);
 
-            for &pool in &chunk {
-                let token0: Address =
-                    match IUniswapV2Pair::token0Call::abi_decode_returns(&res.returnData[i]) {
-                        Ok(addr) => addr,
-                        Err(e) => {
-                            eprintln!("Error decoding token0: {:?}", e);
-                            continue;
-                        }
-                    };
-                i += 1;
-
-                let token1: Address =
-                    match IUniswapV2Pair::token1Call::abi_decode_returns(&res.returnData[i]) {
-                        Ok(addr) => addr,
-                        Err(e) => {
-                            eprintln!("Error decoding token1: {:?}", e);
-                            continue;
-                        }
-                    };
-                i += 1;
-
-                let reserves =
-                    match IUniswapV2Pair::getReservesCall::abi_decode_returns(&res.returnData[i]) {
-                        Ok(res) => res,
-                        Err(e) => {
-                            eprintln!("Error decoding reserves: {:?}", e);
-                            continue;
-                        }
-                    };
-                i += 1;
-
-                let r0 = U256::from(reserves.reserve0);
-                let r1 = U256::from(reserves.reserve1);
-                //fetching tokens 
-                let t0=
-                match load_token(token0, provider_clone.clone(), &cache_clone).await {
-                    Ok(res) => res,
-                    Err(e) => {
-                        eprintln!("Error decoding token0: {:?}", e);
+            for (k, &pool) in chunk.iter().enumerate() {
+                let r = &res[k * per_pool..(k + 1) * per_pool];
+                let decoded =
+                    decode_call::<IUniswapV2Pair::token0Call>(&r[0], "token0").and_then(|token0| {
+                        let token1 = decode_call::<IUniswapV2Pair::token1Call>(&r[1], "token1")?;
+                        let reserves =
+                            decode_call::<IUniswapV2Pair::getReservesCall>(&r[2], "getReserves")?;
+                        Ok((token0, token1, reserves))
+                    });
+                let (token0, token1, reserves) = match decoded {
+                    Ok(v) => v,
+                    Err(reason) => {
+                        eprintln!("Skipping pair {:#x}: {}", pool, reason);
+                        local.skipped.push(SkippedPool { pool, reason });
                         continue;
                     }
                 };
-                let t1=
-                match load_token(token1, provider_clone.clone(), &cache_clone).await {
-                    Ok(res) => res,
-                    Err(e) => {
-                        eprintln!("Error decoding token0: {:?}", e);
+
+                let fee = pair_fee(dex.fee, r.get(3));
+
+                token_addrs.extend([token0, token1]);
+                decoded_pairs.push((pool, token0, token1, reserves, fee));
+            }
+
+            //fetching tokens, all of the batch's unseen ones at once; if that
+            //fails, the pairs whose tokens aren't cached yet are skipped
+            let token_error = load_tokens(
+                provider_clone.clone(),
+                &token_addrs,
+                &cache_clone,
+                BlockId::number(block_number),
+            )
+            .await
+            .err()
+            .map(|e| e.to_string());
+
+            for (pool, token0, token1, reserves, fee) in decoded_pairs {
+                let lookup = |addr: Address| {
+                    cache_clone
+                        .get(&addr)
+                        .map(|t| t.clone())
+                        .ok_or_else(|| match &token_error {
+                            Some(e) => SkipReason::Rpc(e.clone()),
+                            None => SkipReason::Token(addr, "metadata unavailable".to_string()),
+                        })
+                };
+                let (t0, t1) = match lookup(token0).and_then(|t0| Ok((t0, lookup(token1)?))) {
+                    Ok(tokens) => tokens,
+                    Err(reason) => {
+                        eprintln!("Skipping pair {:#x}: {}", pool, reason);
+                        local.skipped.push(SkippedPool { pool, reason });
                         continue;
                     }
                 };
 
-
-                local.push(Pool {
+                local.pools.push(Pool {
                     id: format!("{:#x}", pool),
                     token0: t0,
                     token1: t1,
-                    reserve0: r0.to_string(),
-                    reserve1: r1.to_string(),
+                    reserve0: U256::from(reserves.reserve0).to_string(),
+                    reserve1: U256::from(reserves.reserve1).to_string(),
+                    reserve_units: ReserveUnits::Raw,
                     reserveUSD: None,
+                    fee,
+                    dex: dex.name.to_string(),
+                    block_number: Some(block_number),
+                    synced_at: Some(u64::from(reserves.blockTimestampLast)),
                 });
             }
 
-            Ok(local)
+            local
         }));
-     
-        for h in handles.drain(..) {
-            pool_states.extend(h.await??);
-        }
-    }  
-    Ok(pool_states)
+    }
+
+    // every batch is spawned before any is awaited; the semaphore keeps
+    // at most MAX_CONCURRENCY multicalls in flight
+    for h in handles {
+        let local = h.await?;
+        fetched.pools.extend(local.pools);
+        fetched.skipped.extend(local.skipped);
+    }
+    Ok(fetched)
 }
 
 #[cfg(test)]
 mod tests {
 
-    
-
     use super::*;
+    use crate::config::RpcConfig;
+    use crate::uniswap_v2::UNISWAP_V2;
+    use alloy::providers::{DynProvider, ProviderBuilder, mock::Asserter};
+
+    /// Provider from `RpcConfig::load` (e.g. `ARB_HTTP_URL` pointing at an
+    /// anvil fork); `None`, and the test is skipped, when there is none
+    async fn configured_provider() -> Option<Arc<DynProvider>> {
+        let config = match RpcConfig::load() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("skipping: {}", e);
+                return None;
+            }
+        };
+        Some(
+            config
+                .http_provider()
+                .await
+                .expect("configured RPC endpoint unreachable"),
+        )
+    }
+
     #[tokio::test]
     async fn test_data_fetcher() {
+        let Some(provider) = configured_provider().await else {
+            return;
+        };
         let now = Instant::now();
 
-        let pools = data_fetcher().await;
+        let registry = Registry::in_memory().unwrap();
+        let pools = data_fetcher(provider, &registry, 0).await;
         //assert!(!pools.is_empty());
         for pool in pools.iter().take(5) {
             println!("{:?}", pool.len());
@@ -272,10 +950,104 @@ mod tests {
         println!("Time taken: {} seconds", t.as_secs());
     }
 
+    #[test]
+    fn failed_sub_calls_become_skip_reasons() {
+        let reverted = Call3Result {
+            success: false,
+            returnData: Default::default(),
+        };
+        assert!(matches!(
+            decode_call::<IUniswapV2Pair::token0Call>(&reverted, "token0"),
+            Err(SkipReason::Reverted("token0"))
+        ));
+
+        let garbage = Call3Result {
+            success: true,
+            returnData: vec![1u8; 3].into(),
+        };
+        assert!(matches!(
+            decode_call::<IUniswapV2Pair::token0Call>(&garbage, "token0"),
+            Err(SkipReason::BadReturn("token0", _))
+        ));
+
+        let token = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let ok = Call3Result {
+            success: true,
+            returnData: IUniswapV2Pair::token0Call::abi_encode_returns(&token).into(),
+        };
+        assert_eq!(
+            decode_call::<IUniswapV2Pair::token0Call>(&ok, "token0").unwrap(),
+            token
+        );
+    }
+
+    #[test]
+    fn decodes_per_pair_swap_fees() {
+        // Biswap-style: swapFee() in thousandths
+        let biswap = V2Fee::PairSwapFee {
+            denominator: 1000,
+            fallback: 2000,
+        };
+        let fee = |f: u32| Call3Result {
+            success: true,
+            returnData: IUniswapV2Pair::swapFeeCall::abi_encode_returns(&f).into(),
+        };
+        assert_eq!(pair_fee(biswap, Some(&fee(1))), 1000);
+        assert_eq!(pair_fee(biswap, Some(&fee(3))), 3000);
+        // reverted, missing or a fee of 100% or more: the fallback
+        let reverted = Call3Result {
+            success: false,
+            returnData: Default::default(),
+        };
+        assert_eq!(pair_fee(biswap, Some(&reverted)), 2000);
+        assert_eq!(pair_fee(biswap, None), 2000);
+        assert_eq!(pair_fee(biswap, Some(&fee(1000))), 2000);
+        assert_eq!(pair_fee(V2Fee::Fixed(2500), Some(&fee(1))), 2500);
+    }
+
+    #[test]
+    fn decodes_non_standard_metadata() {
+        let ok = |data: Vec<u8>| Call3Result {
+            success: true,
+            returnData: data.into(),
+        };
+
+        // MKR: symbol() returns bytes32
+        let mut mkr = [0u8; 32];
+        mkr[..3].copy_from_slice(b"MKR");
+        assert_eq!(decode_text(&ok(mkr.to_vec())), "MKR");
+        assert_eq!(decode_text(&ok("Dai".to_string().abi_encode())), "Dai");
+        assert_eq!(
+            decode_text(&Call3Result {
+                success: false,
+                returnData: Default::default()
+            }),
+            ""
+        );
+
+        let missing = Call3Result {
+            success: false,
+            returnData: Default::default(),
+        };
+        assert_eq!(decode_decimals(&missing), (18, DecimalsStatus::Missing));
+        assert_eq!(
+            decode_decimals(&ok(U256::from(6).abi_encode())),
+            (6, DecimalsStatus::Reported)
+        );
+        assert_eq!(
+            decode_decimals(&ok(U256::from(255).abi_encode())).1,
+            DecimalsStatus::Implausible
+        );
+        assert_eq!(
+            decode_decimals(&ok(U256::MAX.abi_encode())),
+            (u8::MAX, DecimalsStatus::Implausible)
+        );
+    }
+
     //test building multicall calldata
     #[tokio::test]
     async fn test_build_all_pairs_calls() {
-        let factory = UNISWAP_V2_FACTORY;
+        let factory = UNISWAP_V2.factory;
         let calls = build_all_pairs_calls(U256::from(0), 5, factory);
         assert_eq!(calls.len(), 5);
         for (i, call) in calls.iter().enumerate() {
@@ -286,37 +1058,193 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_poolfetcher() -> Result<(), Box<dyn std::error::Error>> {
-        let rpc_url = "https://reth-ethereum.ithaca.xyz/rpc".parse()?;
+    async fn lists_pairs_through_a_mock_transport() {
+        let asserter = Asserter::new();
+        let provider = Arc::new(ProviderBuilder::new().connect_mocked_client(asserter.clone()));
+        let pairs = [
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        ];
+        let aggregate = IMulticall2::aggregateReturn {
+            _0: U256::from(7),
+            returnData: pairs
+                .iter()
+                .map(|p| IUniswapV2Factory::allPairsCall::abi_encode_returns(p).into())
+                .collect(),
+        };
+        // first attempt fails, the retry gets the batch
+        asserter.push_failure_msg("rate limited");
+        asserter.push_success(&alloy::primitives::Bytes::from(
+            IMulticall2::aggregateCall::abi_encode_returns(&aggregate),
+        ));
+
+        let registry = Registry::in_memory().unwrap();
+        let listed = fetch_pools(
+            provider,
+            UNISWAP_V2.factory,
+            BlockId::number(7),
+            0,
+            3,
+            |first, batch| registry.record_pairs(&UNISWAP_V2, first, batch),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listed, pairs);
+        assert_eq!(registry.pairs_listed(&UNISWAP_V2).unwrap(), 3);
+    }
 
-        let provider = ProviderBuilder::new().connect_http(rpc_url);
+    #[tokio::test]
+    async fn each_batch_is_recorded_before_the_next_is_listed() {
+        let asserter = Asserter::new();
+        let provider = Arc::new(ProviderBuilder::new().connect_mocked_client(asserter.clone()));
+        let aggregate = IMulticall2::aggregateReturn {
+            _0: U256::from(7),
+            returnData: (0..PAIRS_BATCH)
+                .map(|i| {
+                    IUniswapV2Factory::allPairsCall::abi_encode_returns(&Address::with_last_byte(
+                        i as u8,
+                    ))
+                    .into()
+                })
+                .collect(),
+        };
+        // only the first batch is answered: the listing must stop (here,
+        // interrupted by the callback) with that batch already recorded
+        asserter.push_success(&alloy::primitives::Bytes::from(
+            IMulticall2::aggregateCall::abi_encode_returns(&aggregate),
+        ));
+
+        let registry = Registry::in_memory().unwrap();
+        let mut batches = 0;
+        let result = fetch_pools(
+            provider,
+            UNISWAP_V2.factory,
+            BlockId::number(7),
+            0,
+            PAIRS_BATCH + 3,
+            |first, batch| {
+                batches += 1;
+                registry.record_pairs(&UNISWAP_V2, first, batch)?;
+                eyre::bail!("stop after the first batch")
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(batches, 1);
+        assert_eq!(registry.pairs_listed(&UNISWAP_V2).unwrap(), PAIRS_BATCH);
+    }
 
-        let provider = Arc::new(provider);
-        let factory = UNISWAP_V2_FACTORY;
-        let result = fetch_pools(provider.clone(), factory, 10).await;
+    #[tokio::test]
+    async fn a_failed_batch_only_skips_its_own_pairs() {
+        let asserter = Asserter::new();
+        let provider = Arc::new(ProviderBuilder::new().connect_mocked_client(asserter.clone()));
+        let (a, b) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        // tokens already known: no metadata calls
+        let cache: Arc<DashMap<Address, Token>> = Arc::new(DashMap::new());
+        for t in [a, b] {
+            cache.insert(
+                t,
+                Token {
+                    id: format!("{:#x}", t),
+                    symbol: String::new(),
+                    name: String::new(),
+                    decimals: 18,
+                    decimals_status: DecimalsStatus::Reported,
+                },
+            );
+        }
+        let ok = |data: Vec<u8>| Call3Result {
+            success: true,
+            returnData: data.into(),
+        };
+        let reserves = IUniswapV2Pair::getReservesReturn {
+            reserve0: alloy::primitives::aliases::U112::from(1000),
+            reserve1: alloy::primitives::aliases::U112::from(2000),
+            blockTimestampLast: 1_700_000_000,
+        };
+        let batch: Vec<Call3Result> = (0..ADDR_BATCH)
+            .flat_map(|_| {
+                [
+                    ok(IUniswapV2Pair::token0Call::abi_encode_returns(&a)),
+                    ok(IUniswapV2Pair::token1Call::abi_encode_returns(&b)),
+                    ok(IUniswapV2Pair::getReservesCall::abi_encode_returns(
+                        &reserves,
+                    )),
+                ]
+            })
+            .collect();
+        // two batches in flight: whichever asks first fails, and keeps
+        // failing through every retry; the other decodes
+        asserter.push_failure_msg("rate limited");
+        asserter.push_success(&alloy::primitives::Bytes::from(
+            IMulticall3::aggregate3Call::abi_encode_returns(&batch),
+        ));
+        for _ in 0..MAX_RETRIES {
+            asserter.push_failure_msg("rate limited");
+        }
+
+        let pairs: Vec<Address> = (1..=2 * ADDR_BATCH as u8)
+            .map(Address::with_last_byte)
+            .collect();
+        let fetched = fetch_pool_states(provider, &pairs, cache, UNISWAP_V2, 7)
+            .await
+            .unwrap();
+        assert_eq!(fetched.pools.len(), ADDR_BATCH);
+        assert_eq!(fetched.pools[0].synced_at, Some(1_700_000_000));
+        assert_eq!(fetched.skipped.len(), ADDR_BATCH);
+        assert!(
+            fetched
+                .skipped
+                .iter()
+                .all(|s| matches!(s.reason, SkipReason::Rpc(_)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poolfetcher() -> Result<(), Box<dyn std::error::Error>> {
+        let Some(provider) = configured_provider().await else {
+            return Ok(());
+        };
+        let factory = UNISWAP_V2.factory;
+        let block_number = provider.get_block_number().await?;
+        let result = fetch_pools(
+            provider.clone(),
+            factory,
+            BlockId::number(block_number),
+            0,
+            10,
+            |_, _| Ok(()),
+        )
+        .await;
         let cache: Arc<DashMap<Address, Token>> = Arc::new(DashMap::new());
 
         match result {
             Ok(pools) => {
                 println!("Fetched pools: {:?}", pools);
-                let pool_states=fetch_pool_states(provider.clone(), &pools, cache).await?;
-                println!("{:#?}",pool_states)
+                let pool_states =
+                    fetch_pool_states(provider.clone(), &pools, cache, UNISWAP_V2, block_number)
+                        .await?;
+                println!("{:#?}", pool_states)
             }
             Err(e) => {
                 eprintln!("Error fetching pools: {:?}", e);
             }
         }
-        
+
         Ok(())
     }
     #[tokio::test]
     async fn final_test() -> Result<(), anyhow::Error> {
-        match data_fetcher().await {
+        let Some(provider) = configured_provider().await else {
+            return Ok(());
+        };
+        let registry = Registry::in_memory().unwrap();
+        match data_fetcher(provider, &registry, 0).await {
             Ok(pools) => {
                 println!("Fetched pools: ");
-                println!("{:#?}",pools);
-                
-                
+                println!("{:#?}", pools);
+
                 Ok(())
             }
             Err(e) => {
@@ -1,25 +1,108 @@
-use serde::Deserialize;
+use alloy::primitives::U256;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::oracle::PriceOracle;
+use crate::pool::{AmmPool, whole_tokens};
+use crate::quote::verify_cycle;
+
+/// Uniswap V2 swap fee, in hundredths of a bip (0.3%)
+pub const UNISWAP_V2_FEE: u32 = 3000;
+
 /// -------------------------------
 /// Data Models (from subgraph)
 /// -------------------------------
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Token {
     pub symbol: String,
     pub name: String,
     pub id: String,
-    pub decimals: String,
+    #[serde(deserialize_with = "deserialize_decimals")]
+    pub decimals: u8,
+    #[serde(default)] // not in the subgraph; its decimals are taken as reported
+    pub decimals_status: DecimalsStatus,
+}
+
+/// Largest `decimals()` taken at face value; Curve's rate math
+/// (10^(36 - decimals)) breaks down past this
+pub const MAX_PLAUSIBLE_DECIMALS: u8 = 36;
+
+/// Whether `Token.decimals` came from the token itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DecimalsStatus {
+    /// `decimals()` answered with a plausible value
+    #[default]
+    Reported,
+    /// No `decimals()` (reverted or empty); `decimals` is assumed 18
+    Missing,
+    /// `decimals()` answered above `MAX_PLAUSIBLE_DECIMALS`; kept as
+    /// reported, saturated at 255
+    Implausible,
+}
+
+/// The subgraph sends decimals as a string ("18"), snapshots as a number
+fn deserialize_decimals<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Decimals {
+        Number(u8),
+        Text(String),
+    }
+    // untagged needs a self-describing format; binary snapshots aren't
+    if !deserializer.is_human_readable() {
+        return u8::deserialize(deserializer);
+    }
+    match Decimals::deserialize(deserializer)? {
+        Decimals::Number(decimals) => Ok(decimals),
+        Decimals::Text(text) => text.trim().parse().map_err(serde::de::Error::custom),
+    }
+}
+
+impl Token {
+    /// True if `decimals` can be used to scale amounts.
+    pub fn decimals_trusted(&self) -> bool {
+        self.decimals_status == DecimalsStatus::Reported
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pool {
     pub id: String,
     pub token0: Token,
     pub token1: Token,
-    pub reserve0: String, // from subgraph: already scaled (not raw 1e18)
-    pub reserve1: String, // from subgraph: already scaled
+    pub reserve0: String, // in `reserve_units`
+    pub reserve1: String,
+    #[serde(default)] // subgraph pairs carry no flag and are scaled
+    pub reserve_units: ReserveUnits,
     #[serde(default)]
     pub reserveUSD: Option<String>, // optional, if you add it to the query
+    #[serde(default = "default_v2_fee")]
+    pub fee: u32, // hundredths of a bip: 3000 = 0.3%, 2500 = 0.25%
+    #[serde(default = "default_dex")]
+    pub dex: String, // `V2Factory.name` of the factory that created the pair
+    #[serde(default)]
+    pub block_number: Option<u64>, // block the reserves were read at (None: subgraph)
+    #[serde(default)]
+    pub synced_at: Option<u64>, // unix time of the pair's last Sync (getReserves' blockTimestampLast)
+}
+
+/// How a `Pool`'s reserves are denominated; set by whatever produced it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ReserveUnits {
+    /// Integer base units, as `getReserves` returns them (on-chain fetch)
+    Raw,
+    /// Whole tokens, already divided by 10^decimals (subgraph)
+    #[default]
+    Scaled,
+}
+
+fn default_v2_fee() -> u32 {
+    UNISWAP_V2_FEE
+}
+
+fn default_dex() -> String {
+    "uniswap_v2".to_string()
 }
 
 /// Directed edge between tokens (price-based)
@@ -27,25 +110,64 @@ pub struct Pool {
 pub struct DirEdge {
     pub from: String,
     pub to: String,
-    pub rate: f64,   // effective small-trade rate (includes fee)
+    pub rate: f64, // small-trade rate in whole tokens (decimal-normalized, includes fee)
     pub weight: f64, // -ln(rate)
     pub pool_id: String,
+    pub zero_for_one: bool, // true if `from` precedes `to` in pool order (token0 → token1)
+    pub fee: f64,           // pool fee baked into `rate`
 }
 
 /// Token graph for arbitrage (external API type)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Network {
-    pub tokens: Vec<String>, // token IDs
-    pub edges: Vec<DirEdge>, // logical edges (string-based)
+    pub tokens: Vec<String>,          // token IDs
+    pub edges: Vec<DirEdge>,          // logical edges (string-based)
+    pub pools: Vec<Arc<dyn AmmPool>>, // pools backing the edges, for exact re-pricing
+    pub prices: PriceOracle,          // USD per whole token, from the V2 pools; ranks cycles
+}
+
+impl Network {
+    /// Pools by id, for resolving many cycles with `hop_pools`
+    pub fn pools_by_id(&self) -> HashMap<&str, Arc<dyn AmmPool>> {
+        self.pools.iter().map(|p| (p.id(), p.clone())).collect()
+    }
+}
+
+/// One leg of a cycle: the exact pool edge that was traded
+#[derive(Debug, Clone)]
+pub struct CycleHop {
+    pub pool_id: String,
+    pub token_in: String,
+    pub token_out: String,
+    pub zero_for_one: bool, // true if token_in is the pool's token0
+    pub fee: f64,
+    pub rate: f64, // small-trade rate of this edge, in whole tokens (includes fee)
+}
+
+impl CycleHop {
+    fn from_edge(e: &DirEdge) -> Self {
+        CycleHop {
+            pool_id: e.pool_id.clone(),
+            token_in: e.from.clone(),
+            token_out: e.to.clone(),
+            zero_for_one: e.zero_for_one,
+            fee: e.fee,
+            rate: e.rate,
+        }
+    }
 }
 
 /// Result of an arbitrage cycle
 #[derive(Debug, Clone)]
 pub struct ArbitrageCycle {
     pub start_token: String,
-    pub path: Vec<String>, // token IDs in cycle order
-    pub product: f64,      // ∏ rate along cycle
-    pub profit_pct: f64,   // (product - 1) * 100
+    pub path: Vec<String>,   // token IDs in cycle order
+    pub hops: Vec<CycleHop>, // path.len() - 1 legs, in trade order
+    pub product: f64,        // ∏ rate along cycle
+    pub profit_pct: f64,     // (product - 1) * 100
+    pub amount_in: U256,     // optimal input, start-token wei
+    pub amount_out: U256,    // exact output from getAmountOut per hop
+    pub profit: U256,        // amount_out - amount_in
 }
 
 /// -------------------------------
@@ -57,7 +179,8 @@ struct IndexedEdge {
     to: usize,
     rate: f64,
     weight: f64,
-    pool_id: String,
+    src: usize,  // index into Network.edges
+    pool: usize, // index into Network.pools
 }
 
 #[derive(Debug, Clone)]
@@ -75,10 +198,17 @@ fn index_network(network: &Network) -> IndexedNetwork {
         token_to_idx.insert(t.clone(), i);
     }
 
+    let pool_slot: HashMap<&str, usize> = network
+        .pools
+        .iter()
+        .enumerate()
+        .map(|(slot, p)| (p.id(), slot))
+        .collect();
+
     let mut edges: Vec<IndexedEdge> = Vec::with_capacity(network.edges.len());
     let mut adj: Vec<Vec<usize>> = vec![Vec::new(); n];
 
-    for e in &network.edges {
+    for (src, e) in network.edges.iter().enumerate() {
         let from_idx = match token_to_idx.get(&e.from) {
             Some(&idx) => idx,
             None => continue,
@@ -87,6 +217,10 @@ fn index_network(network: &Network) -> IndexedNetwork {
             Some(&idx) => idx,
             None => continue,
         };
+        let pool = match pool_slot.get(e.pool_id.as_str()) {
+            Some(&slot) => slot,
+            None => continue,
+        };
 
         let edge_index = edges.len();
         edges.push(IndexedEdge {
@@ -94,7 +228,8 @@ fn index_network(network: &Network) -> IndexedNetwork {
             to: to_idx,
             rate: e.rate,
             weight: e.weight,
-            pool_id: e.pool_id.clone(),
+            src,
+            pool,
         });
         adj[from_idx].push(edge_index);
     }
@@ -107,94 +242,84 @@ fn index_network(network: &Network) -> IndexedNetwork {
 }
 
 /// ------------------------------------------------------------
-/// 1️⃣ Construct network (Uniswap V2, TVL-filtered, no decimals)
+/// 1️⃣ Construct network (any AMM, via `AmmPool`)
 /// ------------------------------------------------------------
+/// Network over Uniswap V2 pairs; other pools are added with `add_pools`.
 pub fn construct_network(pools: &Vec<Pool>) -> Network {
-    let mut edges: Vec<DirEdge> = Vec::new();
-    let mut token_set: HashSet<String> = HashSet::new();
-
-    // Uniswap V2 fee
-    let fee = 0.003_f64;
-    let one_minus_fee = 1.0 - fee;
-
-    // simple TVL cutoff (can tune or remove if you want everything)
-    const MIN_TVL_USD: f64 = 10_000.0;
-
-    for p in pools {
-        // Skip zero-address garbage
-        // if p.token0.id == "0x0000000000000000000000000000000000000000"
-        //     || p.token1.id == "0x0000000000000000000000000000000000000000"
-        // {
-        //     continue;
-        // }
-
-        // Parse reserves (already scaled from subgraph)
-        let reserve0: f64 = match p.reserve0.parse() {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        let reserve1: f64 = match p.reserve1.parse() {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-
-        if reserve0 <= 0.0 || reserve1 <= 0.0 {
-            continue;
-        }
+    let mut network = Network {
+        prices: PriceOracle::from_pools(pools),
+        ..Network::default()
+    };
+    add_pools(&mut network, pools.iter().cloned());
+    network
+}
 
-        // // Optional TVL filter if reserveUSD is present
-        // if let Some(s) = &p.reserveUSD {
-        //     if let Ok(tvl) = s.parse::<f64>() {
-        //         if tvl < MIN_TVL_USD {
-        //             continue;
-        //         }
-        //     }
-        // }
+/// Default `LiquidityFilter.min_tvl_usd`
+pub const MIN_TVL_USD: f64 = 10_000.0;
 
-        // Uniswap V2 mid-price: token0 → token1
-        // reserves are already in real token units, so no decimal adjustment.
-        let mid_price_0to1 = reserve1 / reserve0;
+/// How much USD liquidity a pool needs to enter the network. Dust pools
+/// quote absurd mid-prices that show up as huge phantom cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LiquidityFilter {
+    pub min_tvl_usd: f64,  // both sides together
+    pub min_side_usd: f64, // each side on its own
+}
 
-        if !mid_price_0to1.is_finite() || mid_price_0to1 <= 0.0 {
-            continue;
+impl Default for LiquidityFilter {
+    fn default() -> Self {
+        LiquidityFilter {
+            min_tvl_usd: MIN_TVL_USD,
+            min_side_usd: 0.0,
         }
+    }
+}
 
-        // Small-trade effective rates including fee (approximate)
-        let rate_0to1 = one_minus_fee * mid_price_0to1;
-        let rate_1to0 = one_minus_fee / mid_price_0to1;
+impl LiquidityFilter {
+    /// True if `pool` clears both thresholds at `oracle` prices. A pool
+    /// the oracle can't value falls back to its reported TVL (a V2
+    /// `reserveUSD`), and fails any per-side threshold.
+    pub fn keeps(&self, pool: &dyn AmmPool, oracle: &PriceOracle) -> bool {
+        let sides = oracle.holdings_usd(pool);
+        let tvl = match &sides {
+            Some(sides) => Some(sides.iter().sum()),
+            None => pool.reported_tvl_usd(),
+        };
+        let side_ok = match &sides {
+            Some(sides) => sides.iter().all(|&usd| usd >= self.min_side_usd),
+            None => self.min_side_usd <= 0.0,
+        };
+        side_ok && tvl.unwrap_or(0.0) >= self.min_tvl_usd
+    }
+}
 
-        if !(rate_0to1.is_finite() && rate_0to1 > 0.0) {
+/// Add pools of any AMM and their edges, keeping `tokens` sorted and
+/// unique. Pools that produce no edges are left out.
+pub fn add_pools<P: AmmPool + 'static>(network: &mut Network, pools: impl IntoIterator<Item = P>) {
+    add_shared_pools(
+        network,
+        pools.into_iter().map(|p| Arc::new(p) as Arc<dyn AmmPool>),
+    );
+}
+
+/// `add_pools` for pools already behind an `Arc`
+pub fn add_shared_pools(network: &mut Network, pools: impl IntoIterator<Item = Arc<dyn AmmPool>>) {
+    let mut token_set: HashSet<String> = network.tokens.drain(..).collect();
+
+    for pool in pools {
+        let edges = pool.edges();
+        if edges.is_empty() {
             continue;
         }
-        if !(rate_1to0.is_finite() && rate_1to0 > 0.0) {
-            continue;
+        for e in &edges {
+            token_set.insert(e.from.clone());
+            token_set.insert(e.to.clone());
         }
-
-        // Add both directions
-        edges.push(DirEdge {
-            from: p.token0.id.clone(),
-            to: p.token1.id.clone(),
-            rate: rate_0to1,
-            weight: -rate_0to1.ln(),
-            pool_id: p.id.clone(),
-        });
-
-        edges.push(DirEdge {
-            from: p.token1.id.clone(),
-            to: p.token0.id.clone(),
-            rate: rate_1to0,
-            weight: -rate_1to0.ln(),
-            pool_id: p.id.clone(),
-        });
-
-        token_set.insert(p.token0.id.clone());
-        token_set.insert(p.token1.id.clone());
+        network.edges.extend(edges);
+        network.pools.push(pool);
     }
 
-    let mut tokens: Vec<String> = token_set.into_iter().collect();
-    tokens.sort();
-
-    Network { tokens, edges }
+    network.tokens = token_set.into_iter().collect();
+    network.tokens.sort();
 }
 
 /// ------------------------------------------------------------
@@ -211,20 +336,22 @@ pub fn find_arbitrage(network: &Network, min_profit: f64) -> Vec<ArbitrageCycle>
         return results;
     }
 
-    // dist and pred indexed by node (super-source: all zeros)
+    // dist and pred_edge indexed by node (super-source: all zeros);
+    // pred_edge keeps the exact edge that last relaxed the node, so
+    // parallel pools on the same pair stay distinguishable
     let mut dist = vec![0.0_f64; n];
-    let mut pred = vec![usize::MAX; n];
+    let mut pred_edge = vec![usize::MAX; n];
 
     // Bellman–Ford: V-1 relaxations over all edges
     for _ in 0..(n - 1) {
         let mut updated = false;
-        for e in &idx_net.edges {
+        for (ei, e) in idx_net.edges.iter().enumerate() {
             let du = dist[e.from];
             let dv = dist[e.to];
             let cand = du + e.weight;
             if cand < dv {
                 dist[e.to] = cand;
-                pred[e.to] = e.from;
+                pred_edge[e.to] = ei;
                 updated = true;
             }
         }
@@ -246,91 +373,503 @@ pub fn find_arbitrage(network: &Network, min_profit: f64) -> Vec<ArbitrageCycle>
             }
             seen_cycle_node[cycle_node] = true;
 
-            let cycle_indices = reconstruct_cycle_indices(cycle_node, &pred);
-            if cycle_indices.len() < 3 {
+            let cycle = reconstruct_cycle_edges(cycle_node, &pred_edge, &idx_net);
+            if cycle.len() < 2 {
                 continue;
             }
 
-            // sanity: ensure it's a closed loop
-            let mut cycle = cycle_indices.clone();
-            if *cycle.first().unwrap() != *cycle.last().unwrap() {
-                cycle.push(*cycle.first().unwrap());
-            }
-
             // optional: avoid insanely long cycles
-            if cycle.len() > 10 {
+            if cycle.len() > 9 {
                 continue;
             }
 
-            // compute product along cycle
-            let product = cycle_product(&cycle, &idx_net);
-            let profit_pct = (product - 1.0) * 100.0;
+            if let Some(candidate) = priced_cycle(&cycle, &idx_net.edges, network, min_profit) {
+                results.push(candidate);
+            }
+        }
+    }
+
+    results
+}
 
-            if profit_pct > min_profit * 100.0 && product.is_finite() && product > 1.0 {
-                // map indices back to token IDs
-                let path = cycle
+/// Common base tokens (lowercase, as stored in `Token.id`): WETH, USDC, USDT, DAI.
+pub const DEFAULT_BASE_TOKENS: [&str; 4] = [
+    "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+    "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+    "0xdac17f958d2ee523a2206206994597c13d831ec7",
+    "0x6b175474e89094c44da98e9d2ed5b1d0800f2fea",
+];
+
+/// ------------------------------------------------------------
+/// 3️⃣ Cycle enumeration (every simple cycle ≤ max_hops via base tokens)
+/// ------------------------------------------------------------
+/// Unlike `find_arbitrage`, which keeps a single predecessor per node and so
+/// reports at most one cycle per relaxing node, this lists every simple
+/// cycle of 2..=max_hops hops that starts and ends at one of `base_tokens`.
+/// Rotations of the same cycle are reported once (from the first base token
+/// that reaches it). Results are ranked by USD profit, best first.
+pub fn enumerate_arbitrage(
+    network: &Network,
+    base_tokens: &[String],
+    max_hops: usize,
+    min_profit: f64,
+) -> Vec<ArbitrageCycle> {
+    let idx_net = index_network(network);
+    let mut results: Vec<ArbitrageCycle> = candidate_cycles(&idx_net, base_tokens, max_hops)
+        .iter()
+        .filter_map(|cycle| priced_cycle(cycle, &idx_net.edges, network, min_profit))
+        .collect();
+
+    rank_by_profit(&mut results, network);
+    results
+}
+
+/// Best first: largest profit in USD (`profit` over the start token's
+/// decimals, at `network.prices`), then largest `profit_pct`, so a deep
+/// pool's thin spread outranks a wide spread on dust and a 6-decimal
+/// start token isn't outranked by an 18-decimal one's wei. Cycles whose
+/// start token has no price come after, by profit in whole tokens.
+fn rank_by_profit(cycles: &mut [ArbitrageCycle], network: &Network) {
+    let tokens: HashMap<&str, &Token> = network
+        .pools
+        .iter()
+        .flat_map(|p| p.tokens())
+        .map(|t| (t.id.as_str(), t))
+        .collect();
+    // (USD, whole tokens) of a cycle's profit
+    let value = |c: &ArbitrageCycle| {
+        let whole = tokens
+            .get(c.start_token.as_str())
+            .map_or(0.0, |t| whole_tokens(f64::from(c.profit), t));
+        (
+            network.prices.price(&c.start_token).map(|p| whole * p),
+            whole,
+        )
+    };
+    cycles.sort_by(|a, b| {
+        let ((usd_a, whole_a), (usd_b, whole_b)) = (value(a), value(b));
+        let by_value = match (usd_a, usd_b) {
+            (Some(x), Some(y)) => y.total_cmp(&x),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => whole_b.total_cmp(&whole_a),
+        };
+        by_value.then(b.profit_pct.total_cmp(&a.profit_pct))
+    });
+}
+
+/// Every simple cycle of 2..=max_hops hops through one of `base_tokens`,
+/// rotations of the same cycle listed once (from the first base token
+/// that reaches it).
+fn candidate_cycles(
+    idx_net: &IndexedNetwork<'_>,
+    base_tokens: &[String],
+    max_hops: usize,
+) -> Vec<Vec<usize>> {
+    let n = idx_net.tokens.len();
+    let mut candidates = Vec::new();
+
+    if n == 0 || max_hops < 2 {
+        return candidates;
+    }
+
+    let token_to_idx: HashMap<&str, usize> = idx_net
+        .tokens
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.as_str(), i))
+        .collect();
+
+    let mut seen: HashSet<Vec<usize>> = HashSet::new();
+
+    for base in base_tokens {
+        let Some(&start) = token_to_idx.get(base.as_str()) else {
+            continue;
+        };
+
+        let mut cycles = Vec::new();
+        let mut path = Vec::new();
+        let mut on_path = vec![false; n];
+        on_path[start] = true;
+        collect_cycles(
+            idx_net,
+            start,
+            max_hops,
+            &mut path,
+            &mut on_path,
+            &mut cycles,
+        );
+
+        // skip rotations of a cycle already found from another base token
+        candidates.extend(
+            cycles
+                .into_iter()
+                .filter(|cycle| seen.insert(canonical_rotation(cycle))),
+        );
+    }
+
+    candidates
+}
+
+/// DFS for simple cycles back to `start`, as edge-index sequences.
+///
+/// Parallel pools on the same pair yield distinct cycles.
+fn collect_cycles(
+    net: &IndexedNetwork<'_>,
+    start: usize,
+    max_hops: usize,
+    path: &mut Vec<usize>,
+    on_path: &mut [bool],
+    out: &mut Vec<Vec<usize>>,
+) {
+    let u = path.last().map(|&ei| net.edges[ei].to).unwrap_or(start);
+
+    for &ei in &net.adj[u] {
+        let v = net.edges[ei].to;
+        if v == start {
+            if !path.is_empty() {
+                let mut cycle = path.clone();
+                cycle.push(ei);
+                out.push(cycle);
+            }
+        } else if !on_path[v] && path.len() + 1 < max_hops {
+            on_path[v] = true;
+            path.push(ei);
+            collect_cycles(net, start, max_hops, path, on_path, out);
+            path.pop();
+            on_path[v] = false;
+        }
+    }
+}
+
+/// Rotation-invariant key for an edge cycle: start at the min edge index.
+fn canonical_rotation(cycle: &[usize]) -> Vec<usize> {
+    let pivot = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap_or(0);
+    cycle[pivot..]
+        .iter()
+        .chain(&cycle[..pivot])
+        .copied()
+        .collect()
+}
+
+/// ------------------------------------------------------------
+/// 4️⃣ Incremental detector (re-price only cycles through changed pools)
+/// ------------------------------------------------------------
+/// Opportunities that opened or closed in one `ArbitrageDetector::update`
+#[derive(Debug, Clone, Default)]
+pub struct OpportunityDelta {
+    pub appeared: Vec<ArbitrageCycle>,
+    pub vanished: Vec<ArbitrageCycle>,
+}
+
+/// Keeps the indexed graph and the candidate cycles of `enumerate_arbitrage`
+/// between blocks, with the cycles through each edge, so an update only
+/// re-prices cycles that trade through a changed pool.
+///
+/// An update that adds a pool, or a token pair a pool didn't price before,
+/// changes the graph's shape and falls back to a full rebuild.
+#[derive(Debug, Clone)]
+pub struct ArbitrageDetector {
+    network: Network,
+    edges: Vec<IndexedEdge>,
+    pool_index: HashMap<String, usize>, // pool id -> Network.pools slot
+    pool_edges: HashMap<String, Vec<usize>>, // pool id -> its indexed edges
+    cycles: Vec<Vec<usize>>,            // candidate cycles, as edge indices
+    edge_cycles: Vec<Vec<usize>>,       // indexed edge -> cycles through it
+    open: HashMap<usize, ArbitrageCycle>, // cycle -> current opportunity
+    base_tokens: Vec<String>,
+    max_hops: usize,
+    min_profit: f64,
+}
+
+impl ArbitrageDetector {
+    pub fn new(network: Network, base_tokens: &[String], max_hops: usize, min_profit: f64) -> Self {
+        let idx_net = index_network(&network);
+        let cycles = candidate_cycles(&idx_net, base_tokens, max_hops);
+        let edges = idx_net.edges;
+
+        let mut edge_cycles = vec![Vec::new(); edges.len()];
+        for (ci, cycle) in cycles.iter().enumerate() {
+            for &ei in cycle {
+                edge_cycles[ei].push(ci);
+            }
+        }
+        let mut pool_edges: HashMap<String, Vec<usize>> = HashMap::new();
+        for (ei, e) in edges.iter().enumerate() {
+            let pool_id = network.edges[e.src].pool_id.clone();
+            pool_edges.entry(pool_id).or_default().push(ei);
+        }
+        let pool_index = network
+            .pools
+            .iter()
+            .enumerate()
+            .map(|(slot, p)| (p.id().to_string(), slot))
+            .collect();
+
+        let mut detector = ArbitrageDetector {
+            network,
+            edges,
+            pool_index,
+            pool_edges,
+            cycles,
+            edge_cycles,
+            open: HashMap::new(),
+            base_tokens: base_tokens.to_vec(),
+            max_hops,
+            min_profit,
+        };
+        for ci in 0..detector.cycles.len() {
+            if let Some(candidate) = detector.price(ci) {
+                detector.open.insert(ci, candidate);
+            }
+        }
+        detector
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    /// Open opportunities, best first
+    pub fn opportunities(&self) -> Vec<ArbitrageCycle> {
+        let mut open: Vec<ArbitrageCycle> = self.open.values().cloned().collect();
+        rank_by_profit(&mut open, &self.network);
+        open
+    }
+
+    /// Swap in the new state of each changed pool (matched by id) and
+    /// re-price the cycles that trade through it.
+    pub fn update(&mut self, changed: &[Arc<dyn AmmPool>]) -> OpportunityDelta {
+        let mut dirty: HashSet<usize> = HashSet::new();
+
+        for (i, pool) in changed.iter().enumerate() {
+            let fresh = pool.edges();
+            let Some(&slot) = self.pool_index.get(pool.id()) else {
+                if fresh.is_empty() {
+                    continue;
+                }
+                return self.rebuild(&changed[i..]);
+            };
+            let indexed = self.pool_edges.get(pool.id()).cloned().unwrap_or_default();
+            let known = |f: &DirEdge| {
+                indexed
                     .iter()
-                    .map(|&i| idx_net.tokens[i].clone())
-                    .collect::<Vec<_>>();
-
-                results.push(ArbitrageCycle {
-                    start_token: path[0].clone(),
-                    path,
-                    product,
-                    profit_pct,
-                });
+                    .any(|&ei| same_leg(&self.network.edges[self.edges[ei].src], f))
+            };
+            if !fresh.iter().all(known) {
+                return self.rebuild(&changed[i..]);
+            }
+
+            self.network.pools[slot] = pool.clone();
+            for ei in indexed {
+                let src = self.edges[ei].src;
+                match fresh.iter().find(|f| same_leg(&self.network.edges[src], f)) {
+                    Some(f) => self.network.edges[src] = f.clone(),
+                    None => {
+                        // pair no longer priced: no cycle can trade through it
+                        self.network.edges[src].rate = 0.0;
+                        self.network.edges[src].weight = f64::INFINITY;
+                    }
+                }
+                self.edges[ei].rate = self.network.edges[src].rate;
+                self.edges[ei].weight = self.network.edges[src].weight;
+                dirty.extend(&self.edge_cycles[ei]);
+            }
+        }
+
+        let mut delta = OpportunityDelta::default();
+        for ci in dirty {
+            match (self.price(ci), self.open.remove(&ci)) {
+                (Some(candidate), was_open) => {
+                    if was_open.is_none() {
+                        delta.appeared.push(candidate.clone());
+                    }
+                    self.open.insert(ci, candidate);
+                }
+                (None, Some(closed)) => delta.vanished.push(closed),
+                (None, None) => {}
             }
         }
+        delta
     }
 
-    results
+    fn price(&self, ci: usize) -> Option<ArbitrageCycle> {
+        priced_cycle(
+            &self.cycles[ci],
+            &self.edges,
+            &self.network,
+            self.min_profit,
+        )
+    }
+
+    /// Re-index from scratch with `changed` applied; the delta is matched
+    /// up by hops since cycle indices don't survive a rebuild.
+    fn rebuild(&mut self, changed: &[Arc<dyn AmmPool>]) -> OpportunityDelta {
+        let mut pools = self.network.pools.clone();
+        for pool in changed {
+            match self.pool_index.get(pool.id()) {
+                Some(&slot) => pools[slot] = pool.clone(),
+                None => pools.push(pool.clone()),
+            }
+        }
+
+        let mut network = Network {
+            prices: self.network.prices.clone(),
+            ..Network::default()
+        };
+        add_shared_pools(&mut network, pools);
+
+        let before: HashMap<Vec<(String, String)>, ArbitrageCycle> =
+            self.open.drain().map(|(_, c)| (hop_key(&c), c)).collect();
+        *self = ArbitrageDetector::new(network, &self.base_tokens, self.max_hops, self.min_profit);
+        let after: HashSet<Vec<(String, String)>> = self.open.values().map(hop_key).collect();
+
+        OpportunityDelta {
+            appeared: self
+                .open
+                .values()
+                .filter(|c| !before.contains_key(&hop_key(c)))
+                .cloned()
+                .collect(),
+            vanished: before
+                .into_iter()
+                .filter(|(key, _)| !after.contains(key))
+                .map(|(_, c)| c)
+                .collect(),
+        }
+    }
+}
+
+/// Same pool, same direction
+fn same_leg(a: &DirEdge, b: &DirEdge) -> bool {
+    a.pool_id == b.pool_id && a.from == b.from && a.to == b.to
+}
+
+/// Rotation-invariant identity of a cycle: its (pool, token_in) legs,
+/// starting from the smallest.
+fn hop_key(cycle: &ArbitrageCycle) -> Vec<(String, String)> {
+    let legs: Vec<(String, String)> = cycle
+        .hops
+        .iter()
+        .map(|h| (h.pool_id.clone(), h.token_in.clone()))
+        .collect();
+    let pivot = (0..legs.len()).min_by_key(|&i| &legs[i]).unwrap_or(0);
+    legs[pivot..]
+        .iter()
+        .chain(&legs[..pivot])
+        .cloned()
+        .collect()
+}
+
+/// Price an edge cycle and re-check it with exact integer math.
+///
+/// Returns `None` unless the cycle clears `min_profit` on mid-prices and
+/// still pays out a profit under `getAmountOut`.
+fn priced_cycle(
+    cycle: &[usize],
+    edges: &[IndexedEdge],
+    network: &Network,
+    min_profit: f64,
+) -> Option<ArbitrageCycle> {
+    // compute product along cycle
+    let product = cycle_product(cycle, edges);
+    let profit_pct = (product - 1.0) * 100.0;
+
+    if !(profit_pct > min_profit * 100.0 && product.is_finite() && product > 1.0) {
+        return None;
+    }
+
+    // map edges back to pool hops and token IDs
+    let hops = cycle
+        .iter()
+        .map(|&ei| CycleHop::from_edge(&network.edges[edges[ei].src]))
+        .collect::<Vec<_>>();
+    let mut path = vec![hops[0].token_in.clone()];
+    path.extend(hops.iter().map(|h| h.token_out.clone()));
+
+    let mut candidate = ArbitrageCycle {
+        start_token: path[0].clone(),
+        path,
+        hops,
+        product,
+        profit_pct,
+        amount_in: U256::ZERO,
+        amount_out: U256::ZERO,
+        profit: U256::ZERO,
+    };
+
+    // re-price with exact integer math before reporting
+    let pools: Vec<Arc<dyn AmmPool>> = cycle
+        .iter()
+        .map(|&ei| network.pools[edges[ei].pool].clone())
+        .collect();
+    let (amount_in, amount_out) = verify_cycle(&candidate, &pools)?;
+    candidate.amount_in = amount_in;
+    candidate.amount_out = amount_out;
+    candidate.profit = amount_out - amount_in;
+
+    Some(candidate)
+}
+
+/// The pool each hop of `cycle` trades through, in hop order, as
+/// `optimize_cycle` and `verify_cycle` take them. `pools` comes from
+/// `Network::pools_by_id`.
+pub fn hop_pools(
+    cycle: &ArbitrageCycle,
+    pools: &HashMap<&str, Arc<dyn AmmPool>>,
+) -> Option<Vec<Arc<dyn AmmPool>>> {
+    cycle
+        .hops
+        .iter()
+        .map(|h| pools.get(h.pool_id.as_str()).cloned())
+        .collect()
 }
 
-/// Reconstruct a negative cycle in terms of node indices.
+/// Reconstruct a negative cycle as edge indices, in trade order.
 ///
 /// Standard trick:
-/// 1. Walk pred n times from `start` to ensure you land inside the cycle.
+/// 1. Walk pred_edge n times from `start` to ensure you land inside the cycle.
 /// 2. Then walk until you come back to the starting node.
-fn reconstruct_cycle_indices(start: usize, pred: &[usize]) -> Vec<usize> {
-    let n = pred.len();
+/// 3. The walk runs backwards along the edges, so reverse it.
+fn reconstruct_cycle_edges(
+    start: usize,
+    pred_edge: &[usize],
+    net: &IndexedNetwork<'_>,
+) -> Vec<usize> {
+    let n = pred_edge.len();
     let mut v = start;
 
     // Step into the cycle
     for _ in 0..n {
-        if pred[v] == usize::MAX {
+        if pred_edge[v] == usize::MAX {
             return Vec::new();
         }
-        v = pred[v];
+        v = net.edges[pred_edge[v]].from;
     }
 
     // v is now guaranteed to be in a cycle
     let cycle_start = v;
-    let mut cycle = vec![cycle_start];
-    let mut cur = pred[cycle_start];
+    let mut cycle = Vec::new();
+    let mut cur = cycle_start;
 
-    while cur != cycle_start {
-        cycle.push(cur);
-        cur = pred[cur];
+    loop {
+        let ei = pred_edge[cur];
+        cycle.push(ei);
+        cur = net.edges[ei].from;
+        if cur == cycle_start {
+            break;
+        }
     }
-    cycle.push(cycle_start); // close the loop
+    cycle.reverse();
     cycle
 }
 
-/// Compute ∏ rate along cycle indices, using adjacency.
-fn cycle_product(cycle: &[usize], net: &IndexedNetwork<'_>) -> f64 {
+/// Compute ∏ rate along a cycle of edge indices.
+fn cycle_product(cycle: &[usize], edges: &[IndexedEdge]) -> f64 {
     let mut product = 1.0;
 
-    for w in cycle.windows(2) {
-        let u = w[0];
-        let v = w[1];
-
-        // find edge u -> v
-        let ei = match net.adj[u].iter().find(|&&ei| net.edges[ei].to == v) {
-            Some(&ei) => ei,
-            None => return 1.0, // inconsistent graph; treat as no-arb
-        };
-
-        product *= net.edges[ei].rate;
+    for &ei in cycle {
+        product *= edges[ei].rate;
         if !product.is_finite() || product <= 0.0 {
             return 1.0;
         }
@@ -338,3 +877,243 @@ fn cycle_product(cycle: &[usize], net: &IndexedNetwork<'_>) -> f64 {
 
     product
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{E18, pair, token, token_with_decimals};
+
+    #[test]
+    fn subgraph_pairs_deserialize_as_scaled() {
+        let pair: Pool = serde_json::from_str(
+            r#"{ "id": "0xpair", "reserve0": "200000.5", "reserve1": "100.25",
+                 "token0": { "id": "0xusdc", "symbol": "USDC", "name": "USD Coin", "decimals": "6" },
+                 "token1": { "id": "0xweth", "symbol": "WETH", "name": "Wrapped Ether", "decimals": 18 } }"#,
+        )
+        .unwrap();
+        assert_eq!(pair.reserve_units, ReserveUnits::Scaled);
+        assert_eq!((pair.token0.decimals, pair.token1.decimals), (6, 18));
+        assert!(
+            serde_json::from_str::<Token>(
+                r#"{ "id": "x", "symbol": "X", "name": "X", "decimals": "many" }"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn enumerates_overlapping_cycles_once() {
+        // a->b->c->a and a->b->d->a share the a->b leg
+        let pools = vec![
+            pair("ab", "a", "b", 1_000 * E18, 2_000 * E18),
+            pair("bc", "b", "c", 2_000 * E18, 3_000 * E18),
+            pair("ca", "c", "a", 2_700 * E18, 1_000 * E18),
+            pair("bd", "b", "d", 2_000 * E18, 1_000 * E18),
+            pair("da", "d", "a", 900 * E18, 1_000 * E18),
+        ];
+        let network = construct_network(&pools);
+        let bases = vec!["a".to_string(), "b".to_string()];
+        let cycles = enumerate_arbitrage(&network, &bases, 3, 0.0);
+
+        let paths: Vec<Vec<&str>> = cycles
+            .iter()
+            .map(|c| c.path.iter().map(|s| s.as_str()).collect())
+            .collect();
+        assert_eq!(paths.len(), 2, "{paths:?}");
+        assert!(paths.contains(&vec!["a", "b", "c", "a"]));
+        assert!(paths.contains(&vec!["a", "b", "d", "a"]));
+
+        // ranked best first, and every reported cycle is exact-profitable
+        assert!(cycles[0].profit >= cycles[1].profit);
+        assert!(cycles.iter().all(|c| c.amount_out > c.amount_in));
+    }
+
+    #[test]
+    fn ranks_by_absolute_profit() {
+        // ~0.4% spread on deep pools vs ~99% on dust: the deep one pays more
+        let pools = vec![
+            pair("uni", "a", "b", 1_000 * E18, 2_000 * E18),
+            pair("sushi", "a", "b", 1_000 * E18, 1_980 * E18),
+            pair("dust_ab", "a", "c", E18 / 1_000, E18 / 1_000),
+            pair("dust_ba", "a", "c", E18 / 1_000, E18 / 500),
+        ];
+        let network = construct_network(&pools);
+        let cycles = enumerate_arbitrage(&network, &["a".to_string()], 2, 0.0);
+
+        assert_eq!(cycles.len(), 2);
+        assert!(cycles[0].hops.iter().any(|h| h.pool_id == "uni"));
+        assert!(cycles[0].profit > cycles[1].profit);
+        assert!(cycles[0].profit_pct < cycles[1].profit_pct);
+    }
+
+    #[test]
+    fn ranks_by_usd_profit_across_start_token_decimals() {
+        const E6: u128 = 1_000_000;
+        let [_, usdc, _, dai] = DEFAULT_BASE_TOKENS;
+        let usdc_pair = |id: &str, r_usdc: u128| Pool {
+            token0: token_with_decimals(usdc, 6),
+            ..pair(id, usdc, "b", r_usdc, 1_000 * E18)
+        };
+        let pools = vec![
+            // $1M deep, 10% apart: ~$1k, ~1e9 base units
+            usdc_pair("usdc_uni", 1_000_000 * E6),
+            usdc_pair("usdc_sushi", 1_100_000 * E6),
+            // 1 DAI deep, 10% apart: a tenth of a cent, ~7e14 wei
+            pair("dai_uni", dai, "c", E18, E18),
+            pair("dai_sushi", dai, "c", E18 * 11 / 10, E18),
+        ];
+        let network = construct_network(&pools);
+        let cycles = enumerate_arbitrage(&network, &[dai.to_string(), usdc.to_string()], 2, 0.0);
+
+        assert_eq!(cycles.len(), 2);
+        assert_eq!(cycles[0].start_token, usdc);
+        assert_eq!(cycles[1].start_token, dai);
+        assert!(cycles[0].profit < cycles[1].profit);
+    }
+
+    #[test]
+    fn distinguishes_parallel_pools() {
+        // same pair on two DEXes at different prices: buy b cheap, sell dear
+        let pools = vec![
+            pair("uni", "a", "b", 1_000 * E18, 2_000 * E18),
+            pair("sushi", "a", "b", 1_000 * E18, 1_800 * E18),
+        ];
+        let network = construct_network(&pools);
+
+        for cycles in [
+            find_arbitrage(&network, 0.0),
+            enumerate_arbitrage(&network, &["a".to_string()], 2, 0.0),
+        ] {
+            assert_eq!(cycles.len(), 1);
+            let c = &cycles[0];
+            let legs: Vec<(&str, &str, &str)> = c
+                .hops
+                .iter()
+                .map(|h| {
+                    (
+                        h.pool_id.as_str(),
+                        h.token_in.as_str(),
+                        h.token_out.as_str(),
+                    )
+                })
+                .collect();
+            let expected = if c.start_token == "a" {
+                vec![("uni", "a", "b"), ("sushi", "b", "a")]
+            } else {
+                vec![("sushi", "b", "a"), ("uni", "a", "b")]
+            };
+            assert_eq!(legs, expected);
+            assert!(c.amount_out > c.amount_in);
+        }
+    }
+
+    /// Fixed-rate pool, to check a new AMM needs no engine changes
+    #[derive(Debug)]
+    struct PegPool {
+        tokens: [Token; 2],
+    }
+
+    impl AmmPool for PegPool {
+        fn id(&self) -> &str {
+            "peg"
+        }
+
+        fn protocol(&self) -> crate::pool::Protocol {
+            crate::pool::Protocol::Curve
+        }
+
+        fn tokens(&self) -> Vec<&Token> {
+            self.tokens.iter().collect()
+        }
+
+        fn fee(&self) -> f64 {
+            0.001
+        }
+
+        fn spot_rate(&self, _token_in: &str, _token_out: &str) -> Option<f64> {
+            Some(0.999)
+        }
+
+        fn quote(&self, amount_in: U256, _token_in: &str, _token_out: &str) -> Option<U256> {
+            Some(amount_in * U256::from(999) / U256::from(1000))
+        }
+    }
+
+    #[test]
+    fn routes_through_any_amm_pool() {
+        let mut network = construct_network(&vec![pair("ab", "a", "b", 1_000 * E18, 1_100 * E18)]);
+        add_pools(
+            &mut network,
+            [PegPool {
+                tokens: [token("a"), token("b")],
+            }],
+        );
+        assert_eq!(network.edges.len(), 4);
+
+        let cycles = enumerate_arbitrage(&network, &["a".to_string()], 2, 0.0);
+        assert_eq!(cycles.len(), 1);
+        let pools: Vec<&str> = cycles[0].hops.iter().map(|h| h.pool_id.as_str()).collect();
+        assert_eq!(pools, vec!["ab", "peg"]);
+        assert!(cycles[0].amount_out > cycles[0].amount_in);
+    }
+
+    #[test]
+    fn detector_reprices_only_changed_pools() {
+        let pools = vec![
+            pair("uni", "a", "b", 1_000 * E18, 2_000 * E18),
+            pair("sushi", "a", "b", 1_000 * E18, 1_800 * E18),
+            pair("bc", "b", "c", 2_000 * E18, 2_000 * E18),
+        ];
+        let bases = vec!["a".to_string()];
+        let mut detector = ArbitrageDetector::new(construct_network(&pools), &bases, 3, 0.0);
+        assert_eq!(detector.opportunities().len(), 1);
+
+        // an untouched cycle stays open; nothing appears or vanishes
+        let bc: Arc<dyn AmmPool> = Arc::new(pair("bc", "b", "c", 2_100 * E18, 2_000 * E18));
+        let delta = detector.update(&[bc]);
+        assert!(delta.appeared.is_empty() && delta.vanished.is_empty());
+
+        // sushi catches up with uni: the spread closes
+        let sushi: Arc<dyn AmmPool> = Arc::new(pair("sushi", "a", "b", 1_000 * E18, 2_000 * E18));
+        let delta = detector.update(&[sushi]);
+        assert_eq!(delta.vanished.len(), 1);
+        assert!(delta.appeared.is_empty());
+        assert!(detector.opportunities().is_empty());
+
+        // and reopens the other way
+        let sushi: Arc<dyn AmmPool> = Arc::new(pair("sushi", "a", "b", 1_000 * E18, 2_200 * E18));
+        let delta = detector.update(&[sushi]);
+        assert_eq!(delta.appeared.len(), 1);
+        assert_eq!(delta.appeared[0].hops[0].pool_id, "sushi");
+    }
+
+    #[test]
+    fn detector_rebuilds_for_new_pools() {
+        let pools = vec![pair("uni", "a", "b", 1_000 * E18, 2_000 * E18)];
+        let bases = vec!["a".to_string()];
+        let mut detector = ArbitrageDetector::new(construct_network(&pools), &bases, 3, 0.0);
+        assert!(detector.opportunities().is_empty());
+
+        let sushi: Arc<dyn AmmPool> = Arc::new(pair("sushi", "a", "b", 1_000 * E18, 1_800 * E18));
+        let delta = detector.update(&[sushi]);
+        assert_eq!(delta.appeared.len(), 1);
+        assert_eq!(detector.network().pools.len(), 2);
+        assert_eq!(
+            detector.opportunities().len(),
+            enumerate_arbitrage(detector.network(), &bases, 3, 0.0).len()
+        );
+    }
+
+    #[test]
+    fn respects_hop_limit() {
+        let pools = vec![
+            pair("ab", "a", "b", 1_000 * E18, 2_000 * E18),
+            pair("bc", "b", "c", 2_000 * E18, 3_000 * E18),
+            pair("ca", "c", "a", 2_700 * E18, 1_000 * E18),
+        ];
+        let network = construct_network(&pools);
+        let bases = vec!["a".to_string()];
+        assert!(enumerate_arbitrage(&network, &bases, 2, 0.0).is_empty());
+        assert_eq!(enumerate_arbitrage(&network, &bases, 3, 0.0).len(), 1);
+    }
+}
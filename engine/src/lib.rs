@@ -1,4 +1,21 @@
 // lib.rs
+pub mod balancer;
+pub mod classify;
+pub mod config;
+pub mod curve;
 pub mod datafetcher;
 pub mod engine;
 pub mod executor;
+pub mod live;
+pub mod optimizer;
+pub mod oracle;
+pub mod pool;
+pub mod quality;
+pub mod quote;
+pub mod registry;
+pub mod snapshot;
+pub mod subgraph;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod uniswap_v2;
+pub mod uniswap_v3;
@@ -0,0 +1,593 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, B256, U256},
+    providers::Provider,
+    rpc::types::{Filter, Log},
+    sol,
+    sol_types::SolEvent,
+};
+use eyre::Result;
+use futures_util::StreamExt;
+use tokio::sync::{RwLock, mpsc};
+
+use crate::balancer::BalancerPool;
+use crate::curve::CurvePool;
+use crate::datafetcher::IUniswapV2Pair;
+use crate::engine::{Network, Pool, add_pools, construct_network};
+use crate::pool::AmmPool;
+use crate::snapshot::MarketSnapshot;
+use crate::uniswap_v3::{IUniswapV3Pool, V3Pool};
+
+sol! {
+    interface IUniswapV2PairEvents {
+        event Sync(uint112 reserve0, uint112 reserve1);
+    }
+
+    interface IUniswapV3PoolEvents {
+        event Swap(
+            address indexed sender,
+            address indexed recipient,
+            int256 amount0,
+            int256 amount1,
+            uint160 sqrtPriceX96,
+            uint128 liquidity,
+            int24 tick
+        );
+        event Mint(
+            address sender,
+            address indexed owner,
+            int24 indexed tickLower,
+            int24 indexed tickUpper,
+            uint128 amount,
+            uint256 amount0,
+            uint256 amount1
+        );
+        event Burn(
+            address indexed owner,
+            int24 indexed tickLower,
+            int24 indexed tickUpper,
+            uint128 amount,
+            uint256 amount0,
+            uint256 amount1
+        );
+    }
+}
+
+use IUniswapV2PairEvents::Sync as V2Sync;
+use IUniswapV3PoolEvents::{Burn, Mint, Swap};
+
+/// A tracked pool's state moved at `block_number`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolChanged {
+    pub pool_id: String,
+    pub block_number: u64,
+}
+
+/// -------------------------------
+/// Live market state
+/// -------------------------------
+/// A snapshot kept current from pool events: V2 `Sync` and V3
+/// `Swap`/`Mint`/`Burn` are applied in place. Curve and Balancer pools
+/// stay as loaded.
+#[derive(Debug, Clone, Default)]
+pub struct MarketState {
+    pub block_number: u64, // latest block applied
+    pub v2_pools: HashMap<Address, Pool>,
+    pub v3_pools: HashMap<Address, V3Pool>,
+    pub curve_pools: Vec<CurvePool>,
+    pub balancer_pools: Vec<BalancerPool>,
+    /// Pools whose last applied `Sync`/`Swap` was reorged out; the log
+    /// only carries the new state, so they have to be re-read
+    pub pending_refresh: Vec<Address>,
+}
+
+impl MarketState {
+    pub fn from_snapshot(snapshot: MarketSnapshot) -> Self {
+        let v2_pools = snapshot
+            .v2_pools
+            .into_iter()
+            .filter_map(|p| Some((p.id.parse().ok()?, p)))
+            .collect();
+        let v3_pools = snapshot
+            .v3_pools
+            .into_iter()
+            .filter_map(|p| Some((p.id.parse().ok()?, p)))
+            .collect();
+        MarketState {
+            block_number: snapshot.block_number,
+            v2_pools,
+            v3_pools,
+            curve_pools: snapshot.curve_pools,
+            balancer_pools: snapshot.balancer_pools,
+            pending_refresh: Vec::new(),
+        }
+    }
+
+    /// Token graph over the current state of every pool.
+    pub fn network(&self) -> Network {
+        let v2: Vec<Pool> = self.v2_pools.values().cloned().collect();
+        let mut network = construct_network(&v2);
+        add_pools(&mut network, self.v3_pools.values().cloned());
+        add_pools(&mut network, self.curve_pools.iter().cloned());
+        add_pools(&mut network, self.balancer_pools.iter().cloned());
+        network
+    }
+
+    /// Current state of the given pools, for `ArbitrageDetector::update`
+    pub fn pools<'a>(&self, ids: impl IntoIterator<Item = &'a str>) -> Vec<Arc<dyn AmmPool>> {
+        let mut pools: Vec<Arc<dyn AmmPool>> = Vec::new();
+        for id in ids {
+            let Ok(address) = id.parse::<Address>() else {
+                continue;
+            };
+            if let Some(p) = self.v2_pools.get(&address) {
+                pools.push(Arc::new(p.clone()));
+            } else if let Some(p) = self.v3_pools.get(&address) {
+                pools.push(Arc::new(p.clone()));
+            }
+        }
+        pools
+    }
+
+    /// Log filter for every event `apply_log` understands, on tracked pools.
+    pub fn filter(&self) -> Filter {
+        let addresses: Vec<Address> = self
+            .v2_pools
+            .keys()
+            .chain(self.v3_pools.keys())
+            .copied()
+            .collect();
+        let signatures: Vec<B256> = vec![
+            V2Sync::SIGNATURE_HASH,
+            Swap::SIGNATURE_HASH,
+            Mint::SIGNATURE_HASH,
+            Burn::SIGNATURE_HASH,
+        ];
+        Filter::new().address(addresses).event_signature(signatures)
+    }
+
+    /// Apply one pool event. `Some` if a tracked pool changed; unknown
+    /// pools and unrelated events are ignored.
+    ///
+    /// A reorged-out (`removed`) `Mint` or `Burn` is undone by applying
+    /// the opposite liquidity delta. A removed `Sync` or `Swap` can't be
+    /// undone from the log, so the pool is queued in `pending_refresh`
+    /// instead and `None` returned.
+    pub fn apply_log(&mut self, log: &Log) -> Option<PoolChanged> {
+        if log.removed {
+            return self.revert_log(log);
+        }
+        let address = log.address();
+        let block_number = log.block_number?;
+
+        let topic0 = *log.topic0()?;
+        let pool_id = if topic0 == V2Sync::SIGNATURE_HASH {
+            let pool = self.v2_pools.get_mut(&address)?;
+            let sync = V2Sync::decode_log_data(log.data()).ok()?;
+            pool.reserve0 = sync.reserve0.to_string();
+            pool.reserve1 = sync.reserve1.to_string();
+            pool.block_number = Some(block_number);
+            // not every provider fills the log's block timestamp
+            pool.synced_at = log.block_timestamp.or(pool.synced_at);
+            pool.id.clone()
+        } else if topic0 == Swap::SIGNATURE_HASH {
+            let pool = self.v3_pools.get_mut(&address)?;
+            let swap = Swap::decode_log_data(log.data()).ok()?;
+            pool.sqrt_price_x96 = U256::from(swap.sqrtPriceX96);
+            pool.liquidity = swap.liquidity;
+            pool.tick = swap.tick.as_i32();
+            pool.id.clone()
+        } else if topic0 == Mint::SIGNATURE_HASH {
+            let pool = self.v3_pools.get_mut(&address)?;
+            let mint = Mint::decode_log_data(log.data()).ok()?;
+            let delta = i128::try_from(mint.amount).ok()?;
+            pool.modify_position(mint.tickLower.as_i32(), mint.tickUpper.as_i32(), delta);
+            pool.id.clone()
+        } else if topic0 == Burn::SIGNATURE_HASH {
+            let pool = self.v3_pools.get_mut(&address)?;
+            let burn = Burn::decode_log_data(log.data()).ok()?;
+            if burn.amount == 0 {
+                // fee poke: no liquidity moved
+                return None;
+            }
+            let delta = i128::try_from(burn.amount).ok()?;
+            pool.modify_position(burn.tickLower.as_i32(), burn.tickUpper.as_i32(), -delta);
+            pool.id.clone()
+        } else {
+            return None;
+        };
+
+        self.block_number = self.block_number.max(block_number);
+        Some(PoolChanged {
+            pool_id,
+            block_number,
+        })
+    }
+
+    fn revert_log(&mut self, log: &Log) -> Option<PoolChanged> {
+        let address = log.address();
+        let block_number = log.block_number?;
+        let topic0 = *log.topic0()?;
+        eprintln!(
+            "⚠️ Reverting removed log for {:#x} at block {}",
+            address, block_number
+        );
+
+        let pool_id = if topic0 == Mint::SIGNATURE_HASH {
+            let pool = self.v3_pools.get_mut(&address)?;
+            let mint = Mint::decode_log_data(log.data()).ok()?;
+            let delta = i128::try_from(mint.amount).ok()?;
+            pool.modify_position(mint.tickLower.as_i32(), mint.tickUpper.as_i32(), -delta);
+            pool.id.clone()
+        } else if topic0 == Burn::SIGNATURE_HASH {
+            let pool = self.v3_pools.get_mut(&address)?;
+            let burn = Burn::decode_log_data(log.data()).ok()?;
+            if burn.amount == 0 {
+                return None;
+            }
+            let delta = i128::try_from(burn.amount).ok()?;
+            pool.modify_position(burn.tickLower.as_i32(), burn.tickUpper.as_i32(), delta);
+            pool.id.clone()
+        } else if (topic0 == V2Sync::SIGNATURE_HASH && self.v2_pools.contains_key(&address))
+            || (topic0 == Swap::SIGNATURE_HASH && self.v3_pools.contains_key(&address))
+        {
+            if !self.pending_refresh.contains(&address) {
+                self.pending_refresh.push(address);
+            }
+            return None;
+        } else {
+            return None;
+        };
+
+        Some(PoolChanged {
+            pool_id,
+            block_number,
+        })
+    }
+}
+
+/// (block, log index) of every log the backfill applied, so their copies
+/// on the subscription aren't applied twice
+#[derive(Debug, Default)]
+struct Backfill {
+    through: u64, // last backfilled block
+    applied: HashSet<(u64, u64)>,
+}
+
+impl Backfill {
+    fn record(&mut self, log: &Log) {
+        if let (Some(block), Some(index)) = (log.block_number, log.log_index) {
+            self.through = self.through.max(block);
+            self.applied.insert((block, index));
+        }
+    }
+
+    /// Whether the subscription's `log` was already applied. Once the
+    /// stream is past the backfilled blocks nothing more can repeat.
+    fn covers(&mut self, log: &Log) -> bool {
+        let (Some(block), Some(index)) = (log.block_number, log.log_index) else {
+            return false;
+        };
+        if block > self.through {
+            self.applied.clear();
+            return false;
+        }
+        !log.removed && self.applied.contains(&(block, index))
+    }
+}
+
+/// -------------------------------
+/// Subscription
+/// -------------------------------
+/// Stream pool events for everything in `state` over a pubsub provider,
+/// apply each one, and send a `PoolChanged` per update. Returns when the
+/// subscription ends or the receiver is dropped.
+///
+/// The subscription is opened first, then the logs since `state`'s block
+/// are backfilled with `get_logs`, so nothing between the snapshot and
+/// the subscription is missed; logs both paths deliver are applied once.
+pub async fn watch_pools<P: Provider>(
+    provider: Arc<P>,
+    state: Arc<RwLock<MarketState>>,
+    changes: mpsc::UnboundedSender<PoolChanged>,
+) -> Result<()> {
+    let filter = state.read().await.filter();
+    let subscription = provider.subscribe_logs(&filter).await?;
+    let mut logs = subscription.into_stream();
+
+    let from = state.read().await.block_number + 1;
+    let to = provider.get_block_number().await?;
+    let mut backfill = Backfill::default();
+    if from <= to {
+        let missed = provider
+            .get_logs(&filter.clone().from_block(from).to_block(to))
+            .await?;
+        println!(
+            "⏪ Backfilling {} logs from blocks {}..={}",
+            missed.len(),
+            from,
+            to
+        );
+        for log in &missed {
+            backfill.record(log);
+            if !apply(&provider, &state, &changes, log).await? {
+                return Ok(());
+            }
+        }
+    }
+    println!("👂 Watching pool events...");
+
+    while let Some(log) = logs.next().await {
+        if backfill.covers(&log) {
+            continue;
+        }
+        if !apply(&provider, &state, &changes, &log).await? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Apply `log` and re-read whatever it left pending, sending a
+/// `PoolChanged` for each update. `false` once the receiver is gone.
+async fn apply<P: Provider>(
+    provider: &Arc<P>,
+    state: &RwLock<MarketState>,
+    changes: &mpsc::UnboundedSender<PoolChanged>,
+    log: &Log,
+) -> Result<bool> {
+    let mut updates: Vec<PoolChanged> = state.write().await.apply_log(log).into_iter().collect();
+    updates.extend(refresh_pending(provider, state).await?);
+    Ok(updates
+        .into_iter()
+        .all(|change| changes.send(change).is_ok()))
+}
+
+/// Re-read every pool in `pending_refresh` at the latest block: reserves
+/// for a V2 pair, price, tick and active liquidity for a V3 pool.
+async fn refresh_pending<P: Provider>(
+    provider: &Arc<P>,
+    state: &RwLock<MarketState>,
+) -> Result<Vec<PoolChanged>> {
+    let pending = std::mem::take(&mut state.write().await.pending_refresh);
+    if pending.is_empty() {
+        return Ok(Vec::new());
+    }
+    let block_number = provider.get_block_number().await?;
+    let block = BlockId::number(block_number);
+
+    let mut refreshed = Vec::with_capacity(pending.len());
+    for address in pending {
+        let is_v2 = state.read().await.v2_pools.contains_key(&address);
+        let pool_id = if is_v2 {
+            let reserves = IUniswapV2Pair::new(address, provider.clone())
+                .getReserves()
+                .block(block)
+                .call()
+                .await?;
+            let mut state = state.write().await;
+            let Some(pool) = state.v2_pools.get_mut(&address) else {
+                continue;
+            };
+            pool.reserve0 = U256::from(reserves.reserve0).to_string();
+            pool.reserve1 = U256::from(reserves.reserve1).to_string();
+            pool.block_number = Some(block_number);
+            pool.synced_at = Some(u64::from(reserves.blockTimestampLast));
+            pool.id.clone()
+        } else {
+            let contract = IUniswapV3Pool::new(address, provider.clone());
+            let slot0 = contract.slot0().block(block).call().await?;
+            let liquidity = contract.liquidity().block(block).call().await?;
+            let mut state = state.write().await;
+            let Some(pool) = state.v3_pools.get_mut(&address) else {
+                continue;
+            };
+            pool.sqrt_price_x96 = U256::from(slot0.sqrtPriceX96);
+            pool.tick = slot0.tick.as_i32();
+            pool.liquidity = liquidity;
+            pool.id.clone()
+        };
+        refreshed.push(PoolChanged {
+            pool_id,
+            block_number,
+        });
+    }
+    Ok(refreshed)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{Address, LogData, aliases::I24, aliases::U112, aliases::U160};
+
+    use super::*;
+    use crate::test_support::{pair, v3_pool};
+    use crate::uniswap_v3::sqrt_ratio_at_tick;
+
+    const V2: Address = Address::repeat_byte(0x22);
+    const V3: Address = Address::repeat_byte(0x33);
+
+    fn state() -> MarketState {
+        let v2 = Pool {
+            block_number: Some(1),
+            ..pair(&format!("{:#x}", V2), "a", "b", 1000, 2000)
+        };
+        let v3 = v3_pool(&format!("{:#x}", V3), "a", "b", 0);
+        MarketState::from_snapshot(MarketSnapshot {
+            block_number: 1,
+            timestamp: 0,
+            v2_pools: vec![v2],
+            v3_pools: vec![v3],
+            curve_pools: vec![],
+            balancer_pools: vec![],
+            skipped: vec![],
+        })
+    }
+
+    fn log(address: Address, data: LogData, block_number: u64) -> Log {
+        Log {
+            inner: alloy::primitives::Log { address, data },
+            block_number: Some(block_number),
+            ..Default::default()
+        }
+    }
+
+    fn removed(mut log: Log) -> Log {
+        log.removed = true;
+        log
+    }
+
+    fn mint(lower: i32, upper: i32, amount: u128) -> LogData {
+        Mint {
+            sender: Address::ZERO,
+            owner: Address::ZERO,
+            tickLower: I24::try_from(lower).unwrap(),
+            tickUpper: I24::try_from(upper).unwrap(),
+            amount,
+            amount0: U256::ZERO,
+            amount1: U256::ZERO,
+        }
+        .encode_log_data()
+    }
+
+    #[test]
+    fn sync_replaces_reserves() {
+        let mut state = state();
+        let sync = V2Sync {
+            reserve0: U112::from(5_000),
+            reserve1: U112::from(7_000),
+        };
+        let synced = Log {
+            block_timestamp: Some(1_700_000_108),
+            ..log(V2, sync.encode_log_data(), 9)
+        };
+        let change = state.apply_log(&synced).unwrap();
+
+        assert_eq!(change.pool_id, format!("{:#x}", V2));
+        assert_eq!(change.block_number, 9);
+        let pool = &state.v2_pools[&V2];
+        assert_eq!(
+            (pool.reserve0.as_str(), pool.reserve1.as_str()),
+            ("5000", "7000")
+        );
+        assert_eq!(pool.block_number, Some(9));
+        assert_eq!(pool.synced_at, Some(1_700_000_108));
+        assert_eq!(state.block_number, 9);
+    }
+
+    #[test]
+    fn mint_and_burn_track_in_range_liquidity() {
+        let mut state = state();
+        state.apply_log(&log(V3, mint(-120, 120, 500), 2)).unwrap();
+        // out of range: ticks change, active liquidity doesn't
+        state.apply_log(&log(V3, mint(120, 240, 300), 2)).unwrap();
+        let pool = &state.v3_pools[&V3];
+        assert_eq!(pool.liquidity, 500);
+        assert_eq!(pool.ticks.get(&120), Some(&(-500 + 300)));
+
+        let burn = Burn {
+            owner: Address::ZERO,
+            tickLower: I24::try_from(-120).unwrap(),
+            tickUpper: I24::try_from(120).unwrap(),
+            amount: 500,
+            amount0: U256::ZERO,
+            amount1: U256::ZERO,
+        };
+        state
+            .apply_log(&log(V3, burn.encode_log_data(), 3))
+            .unwrap();
+        let pool = &state.v3_pools[&V3];
+        assert_eq!(pool.liquidity, 0);
+        assert!(!pool.ticks.contains_key(&-120));
+    }
+
+    #[test]
+    fn swap_moves_price() {
+        let mut state = state();
+        let swap = Swap {
+            sender: Address::ZERO,
+            recipient: Address::ZERO,
+            amount0: Default::default(),
+            amount1: Default::default(),
+            sqrtPriceX96: U160::from(sqrt_ratio_at_tick(60).unwrap()),
+            liquidity: 42,
+            tick: I24::try_from(60).unwrap(),
+        };
+        state
+            .apply_log(&log(V3, swap.encode_log_data(), 4))
+            .unwrap();
+        let pool = &state.v3_pools[&V3];
+        assert_eq!((pool.tick, pool.liquidity), (60, 42));
+        assert_eq!(pool.sqrt_price_x96, sqrt_ratio_at_tick(60).unwrap());
+    }
+
+    #[test]
+    fn untracked_logs_are_ignored() {
+        let mut state = state();
+        let sync = V2Sync {
+            reserve0: U112::from(1),
+            reserve1: U112::from(1),
+        };
+        let stranger = Address::repeat_byte(0x44);
+        assert!(
+            state
+                .apply_log(&log(stranger, sync.encode_log_data(), 5))
+                .is_none()
+        );
+        assert!(
+            state
+                .apply_log(&removed(log(stranger, sync.encode_log_data(), 5)))
+                .is_none()
+        );
+        assert!(state.pending_refresh.is_empty());
+    }
+
+    #[test]
+    fn removed_mint_is_undone() {
+        let mut state = state();
+        state.apply_log(&log(V3, mint(-120, 120, 500), 2)).unwrap();
+        let change = state
+            .apply_log(&removed(log(V3, mint(-120, 120, 500), 2)))
+            .unwrap();
+
+        assert_eq!(change.pool_id, format!("{:#x}", V3));
+        let pool = &state.v3_pools[&V3];
+        assert_eq!(pool.liquidity, 0);
+        assert!(pool.ticks.is_empty());
+    }
+
+    #[test]
+    fn removed_sync_queues_a_refresh() {
+        let mut state = state();
+        let sync = V2Sync {
+            reserve0: U112::from(1),
+            reserve1: U112::from(1),
+        };
+        let reorged = removed(log(V2, sync.encode_log_data(), 5));
+        assert!(state.apply_log(&reorged).is_none());
+        assert!(state.apply_log(&reorged).is_none());
+
+        assert_eq!(state.pending_refresh, vec![V2]);
+        assert_eq!(state.v2_pools[&V2].reserve0, "1000");
+    }
+
+    #[test]
+    fn backfilled_logs_are_applied_once() {
+        let at = |block_number, index| Log {
+            log_index: Some(index),
+            ..log(V2, LogData::default(), block_number)
+        };
+        let mut backfill = Backfill::default();
+        backfill.record(&at(5, 0));
+        backfill.record(&at(6, 3));
+
+        assert!(backfill.covers(&at(6, 3)));
+        assert!(!backfill.covers(&at(6, 4)));
+        assert!(!backfill.covers(&removed(at(6, 3))));
+        assert!(!backfill.covers(&at(7, 0)));
+        // past the backfill: the seen set is dropped
+        assert!(!backfill.covers(&at(5, 0)));
+    }
+}
@@ -1,153 +1,246 @@
 use dotenv::dotenv;
-use reqwest;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::env;
 use std::fmt::format;
 use std::fs::File;
 use std::io::Write;
 
-mod engine;
-mod executor;
-
-use ArbEngine::datafetcher::data_fetcher;
-use ArbEngine::engine::{Pool, Token, construct_network, find_arbitrage};
+use ArbEngine::balancer::BALANCER_80BAL_20WETH;
+use ArbEngine::classify::{TokenClassifier, TokenLists, UncheckedPolicy, check_tokens};
+use ArbEngine::config::RpcConfig;
+use ArbEngine::curve::CURVE_POOLS;
+use ArbEngine::datafetcher::{OnChainSource, Verified, add_v3_pools, fetch_snapshot};
+use ArbEngine::engine::{
+    ArbitrageDetector, DEFAULT_BASE_TOKENS, LiquidityFilter, MIN_TVL_USD, enumerate_arbitrage,
+    find_arbitrage, hop_pools,
+};
 use ArbEngine::executor::execute_arbitrage;
-
-/// -------------------------------
-/// GraphQL response models
-/// -------------------------------
-#[derive(Debug, Deserialize)]
-struct GraphQLResponse<T> {
-    data: Option<T>,
-    #[serde(default)]
-    errors: Option<Vec<GraphQLError>>,
-}
-
-#[derive(Debug, Deserialize)]
-struct GraphQLError {
-    message: String,
-    #[serde(default)]
-    locations: Option<Vec<GraphQLErrorLocation>>,
-    #[serde(default)]
-    path: Option<Vec<serde_json::Value>>,
-}
-
-#[derive(Debug, Deserialize)]
-struct GraphQLErrorLocation {
-    line: u32,
-    column: u32,
-}
-
-#[derive(Debug, Deserialize)]
-struct Data {
-    #[serde(rename = "pairs")]
-    pools: Vec<Pool>,
-}
-
-fn construct_graph_ofpools(pools: Vec<Pool>) {
-    println!("Constructing graph with {} pools", pools.len());
+use ArbEngine::live::{MarketState, watch_pools};
+use ArbEngine::optimizer::optimize_cycle;
+use ArbEngine::quality::QualityRules;
+use ArbEngine::registry::{REGISTRY_PATH, Registry};
+use ArbEngine::snapshot::{load_snapshot, save_snapshot};
+use ArbEngine::subgraph::{SubgraphConfig, SubgraphSource};
+use ArbEngine::uniswap_v2::{FactoryFees, V2_FACTORIES};
+
+/// Value following `flag` on the command line
+fn flag_value(flag: &str) -> Option<String> {
+    env::args().skip_while(|a| a != flag).nth(1)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // let endpoint = "https://gateway.thegraph.com/api/subgraphs/id/A3Np3RQbaBA6oKJgiwDJeo5T3zrYfGHPWFYayMwtNDum";
-
-    // let query = r#"
-    // {
-    //   pairs(
-    //     first: 100,
-    //     orderBy: reserveUSD,
-    //     orderDirection: desc,
-    //     where: {
-    //       token0_: { derivedETH_gt: 0 },
-    //       token1_: { derivedETH_gt: 0 }
-    //     }
-    //   ) {
-    //     id
-    //     reserveUSD
-    //     reserve0
-    //     reserve1
-    //     token0 {
-    //       id
-    //       symbol
-    //       name
-    //       derivedETH
-    //       decimals
-    //     }
-    //     token1 {
-    //       id
-    //       symbol
-    //       name
-    //       derivedETH
-    //       decimals
-    //     }
-    //   }
-    // }
-    // "#;
-
-    // let client = reqwest::Client::new();
-    // dotenv().ok();
-    // let api_key = env::var("GRAPH_API_KEY")?;
-    // println!("{}", api_key);
-
-    // let http_resp = client
-    //     .post(endpoint)
-    //     .header("Authorization",format!("Bearer {}", api_key))
-    //     .json(&json!({ "query": query }))
-    //     .send()
-    //     .await?;
-
-    // let status = http_resp.status();
-    // println!("Response Status: {}", status);
-
-    // let body = http_resp.text().await?;
-
-    // if !status.is_success() {
-    //     eprintln!("Non-200 response body: {}", body);
-    //     return Err("Failed to fetch data (non-success HTTP status)".into());
-    // }
-
-    // // Deserialize GraphQL-style response
-    // let gql: GraphQLResponse<Data> = serde_json::from_str(&body)?;
-
-    // if let Some(errors) = &gql.errors {
-    //     eprintln!("GraphQL returned errors:");
-    //     for err in errors {
-    //         eprintln!(" - {}", err.message);
-    //     }
-    //     return Err("GraphQL error; see logs".into());
-    // }
-
-    // let data = gql
-    //     .data
-    //     .ok_or_else(|| "GraphQL response had no `data` field".to_string())?;
+    dotenv().ok();
+    // pairs, token metadata and honeypot verdicts from earlier runs
+    let registry = Registry::open(REGISTRY_PATH)?;
+    // `--discover` lists every factory's new pairs and reads them all;
+    // without it only the pairs the last discovery run kept are re-read
+    // (the first run always discovers)
+    let discover = env::args().any(|a| a == "--discover") || registry.tracked_pair_count()? == 0;
+    let subgraph = env::args().any(|a| a == "--subgraph");
+    // every pool read at one block, so cycles never mix heights
+    // `--snapshot <file>` replays a saved market instead of reading the chain
+    let (mut snapshot, provider) = match flag_value("--snapshot") {
+        Some(path) => {
+            println!("Loading snapshot from {}", path);
+            (load_snapshot(&path)?, None)
+        }
+        None => {
+            // endpoints from rpc.json / ARB_* variables (a .env file works too)
+            let provider = RpcConfig::load()?.http_provider().await?;
+            // per-factory fees from factory_fees.json / ARB_FACTORY_FEES
+            let factories = FactoryFees::load()?.apply(&V2_FACTORIES)?;
+            if subgraph {
+                // `--subgraph`: list pairs from The Graph (ARB_SUBGRAPH_URL,
+                // GRAPH_API_KEY), then re-read each one on-chain
+                let source = Verified {
+                    source: SubgraphSource::new(SubgraphConfig::load()?),
+                    provider: provider.clone(),
+                    factories,
+                };
+                let snapshot = fetch_snapshot(
+                    provider.clone(),
+                    &source,
+                    &CURVE_POOLS,
+                    &[BALANCER_80BAL_20WETH],
+                    &registry,
+                )
+                .await?;
+                (snapshot, Some(provider))
+            } else {
+                // max age 0: nothing older than the pinned block is reused
+                let source = OnChainSource {
+                    provider: provider.clone(),
+                    factories: &factories,
+                    registry: &registry,
+                    max_reserve_age: 0,
+                    discover,
+                };
+                let snapshot = fetch_snapshot(
+                    provider.clone(),
+                    &source,
+                    &CURVE_POOLS,
+                    &[BALANCER_80BAL_20WETH],
+                    &registry,
+                )
+                .await?;
+                (snapshot, Some(provider))
+            }
+        }
+    };
+    // dust, dead and manipulated pairs out first, each with its reason;
+    // `--max-sync-blocks <n>` also drops pairs not synced for n blocks
+    let rules = QualityRules {
+        max_sync_blocks: flag_value("--max-sync-blocks")
+            .map(|v| v.parse())
+            .transpose()?,
+        ..QualityRules::default()
+    };
+    let screened = snapshot.screen(&rules);
+    for (rule, count) in screened.counts() {
+        println!("  {:>15}: {} pools excluded", rule, count);
+    }
+    for excluded in screened.excluded.iter().take(10) {
+        println!("  {} excluded: {}", excluded.pool_id, excluded.reason);
+    }
 
-    // println!("Fetched {} pools from subgraph", data.pools.len());
+    // `--min-tvl <usd>` / `--min-side-liquidity <usd>`: pools below either
+    // (valued from WETH/USDC and stablecoins) stay out of the graph,
+    // and out of the honeypot checks below
+    let filter = LiquidityFilter {
+        min_tvl_usd: flag_value("--min-tvl").map_or(Ok(MIN_TVL_USD), |v| v.parse())?,
+        min_side_usd: flag_value("--min-side-liquidity").map_or(Ok(0.0), |v| v.parse())?,
+    };
+    let dropped = snapshot.retain_liquid(&filter);
+    println!("Dropped {} pools below {:?}", dropped, filter);
+
+    // a discovery run remembers the pairs left so far as the ones later
+    // runs re-read: before token classification, so pairs of tokens not
+    // yet checked stay tracked and can still be checked later
+    if provider.is_some() && discover && !subgraph {
+        registry.track_pairs(&snapshot.v2_pools, snapshot.block_number)?;
+        println!("Tracking {} pairs", snapshot.v2_pools.len());
+    }
 
-    // -----------------------------
-    // Build network + find arb
-    // -----------------------------
-    // let network = construct_network(&data.pools);
+    // tokens on the deny list (token_lists.json / ARB_TOKEN_LISTS) or with
+    // a failed honeypot check stay out; `--check-tokens` simulates a
+    // buy-then-sell on an anvil fork for every liquid WETH-paired token
+    // not yet classified, and caches the verdicts. Tokens neither covers
+    // stay out too, unless `--allow-unchecked`.
+    let mut classifier = TokenClassifier::new(TokenLists::load()?, registry.load_verdicts()?);
+    if env::args().any(|a| a == "--allow-unchecked") {
+        classifier.unchecked_policy = UncheckedPolicy::Allow;
+    }
+    if env::args().any(|a| a == "--check-tokens") {
+        let fork_url = RpcConfig::load()?.http.url;
+        let targets = classifier.unchecked(&snapshot.v2_pools);
+        let verdicts = check_tokens(&fork_url, snapshot.block_number, &targets).await?;
+        registry.save_verdicts(&verdicts, snapshot.block_number)?;
+        classifier.verdicts.extend(verdicts);
+    }
+    let unchecked = snapshot
+        .token_ids()
+        .into_iter()
+        .filter(|t| !classifier.is_classified(t))
+        .count();
+    println!(
+        "{} tokens could not be checked ({:?})",
+        unchecked, classifier.unchecked_policy
+    );
+    let untradable = snapshot.retain_tradable(&classifier);
+    println!(
+        "Dropped {} pools trading denied or honeypot tokens",
+        untradable
+    );
 
-    let pools = data_fetcher().await?;
-    let network = construct_network(&pools);
+    // V3 pools only for the pairs that made it this far: each costs a
+    // getPool per fee tier
+    if let Some(provider) = provider {
+        let added = add_v3_pools(provider, &mut snapshot, &registry).await?;
+        println!("Added {} V3 pools", added);
+        let dropped = snapshot.retain_liquid(&filter);
+        println!("Dropped {} V3 pools below {:?}", dropped, filter);
+    }
+    // `--save-snapshot <file>`: .json for a readable fixture, anything else binary
+    if let Some(path) = flag_value("--save-snapshot") {
+        save_snapshot(&snapshot, &path)?;
+        println!(
+            "Saved snapshot of block {} to {}",
+            snapshot.block_number, path
+        );
+    }
+    let network = snapshot.network();
 
     println!(
-        "Constructed network with {} tokens and {} edges",
+        "Constructed network with {} tokens and {} edges at block {}",
         network.tokens.len(),
-        network.edges.len()
+        network.edges.len(),
+        snapshot.block_number
     );
 
-    let arbitrages = find_arbitrage(&network, 0.0);
+    // `--enumerate` lists every cycle up to 4 hops through the base tokens
+    // instead of one Bellman–Ford cycle per relaxing node
+    let base_tokens: Vec<String> = DEFAULT_BASE_TOKENS.iter().map(|t| t.to_string()).collect();
+    let arbitrages = if env::args().any(|a| a == "--enumerate") {
+        enumerate_arbitrage(&network, &base_tokens, 4, 0.0)
+    } else {
+        find_arbitrage(&network, 0.0)
+    };
     println!("Found {} arbitrage opportunities", arbitrages.len());
 
     let cycles = arbitrages.clone();
+    let pools = network.pools_by_id();
     for arb in arbitrages {
         println!("{:#?}", arb);
+        match hop_pools(&arb, &pools).and_then(|hops| optimize_cycle(&arb, &hops)) {
+            Some(trade) => println!(
+                "  optimal input {:.6} -> output {:.6} (profit {:.6})",
+                trade.amount_in, trade.amount_out, trade.profit
+            ),
+            None => println!("  no profitable trade size"),
+        }
     }
 
     execute_arbitrage(&cycles);
 
+    // `--watch` keeps the pools current from Sync/Swap/Mint/Burn logs and
+    // re-prices only the cycles through pools that moved
+    if env::args().any(|a| a == "--watch") {
+        let mut detector = ArbitrageDetector::new(network, &base_tokens, 4, 0.0);
+        let state = std::sync::Arc::new(tokio::sync::RwLock::new(MarketState::from_snapshot(
+            snapshot,
+        )));
+        let (tx, mut changes) = tokio::sync::mpsc::unbounded_channel();
+        let provider = RpcConfig::load()?.ws_provider().await?;
+        tokio::spawn(watch_pools(provider, state.clone(), tx));
+
+        while let Some(change) = changes.recv().await {
+            // take everything already queued in one pass
+            let mut changed = vec![change];
+            while let Ok(more) = changes.try_recv() {
+                changed.push(more);
+            }
+            let started = std::time::Instant::now();
+            let pools = state
+                .read()
+                .await
+                .pools(changed.iter().map(|c| c.pool_id.as_str()));
+            let delta = detector.update(&pools);
+            println!(
+                "🔄 {} pool updates at block {}: {} new, {} gone in {:?}",
+                changed.len(),
+                changed.last().map_or(0, |c| c.block_number),
+                delta.appeared.len(),
+                delta.vanished.len(),
+                started.elapsed()
+            );
+            for arb in &delta.appeared {
+                println!("  ➕ {:?} ({:.4}%)", arb.path, arb.profit_pct);
+            }
+            for arb in &delta.vanished {
+                println!("  ➖ {:?}", arb.path);
+            }
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,287 @@
+use std::sync::Arc;
+
+use alloy::primitives::U256;
+
+use crate::engine::{ArbitrageCycle, CycleHop};
+use crate::pool::AmmPool;
+use crate::quote::simulate_cycle;
+
+/// Sized trade for a detected cycle, in start-token units
+/// (same units as the pool reserves it was computed from).
+#[derive(Debug, Clone)]
+pub struct CycleTrade {
+    pub amount_in: f64,
+    pub amount_out: f64,
+    pub profit: f64,
+}
+
+/// One constant-product hop, oriented in the direction of the trade.
+#[derive(Debug, Clone, Copy)]
+struct Hop {
+    reserve_in: f64,
+    reserve_out: f64,
+    fee: f64,
+}
+
+/// ------------------------------------------------------------
+/// Optimal input for a cycle (closed form, x*y=k with fee)
+/// ------------------------------------------------------------
+/// Every V2 hop is `out = γ·R_out·x / (R_in + γ·x)`. Composing such maps
+/// stays in the form `out = a·x / (b + c·x)`, so the whole cycle behaves
+/// like a single virtual pool and the profit `out - x` peaks at
+/// `x* = (√(a·b) - b) / c`. Cycles through any pool without
+/// `constant_product_reserves` fall back to a numeric search over exact
+/// quotes. `pools[i]` is the pool of `cycle.hops[i]` (see
+/// `engine::hop_pools`). Returns `None` if `pools` doesn't line up with
+/// the hops or the cycle has no positive-profit trade size.
+pub fn optimize_cycle(cycle: &ArbitrageCycle, pools: &[Arc<dyn AmmPool>]) -> Option<CycleTrade> {
+    if cycle.hops.is_empty() || pools.len() != cycle.hops.len() {
+        return None;
+    }
+
+    let mut hops = Vec::with_capacity(cycle.hops.len());
+    for (h, pool) in cycle.hops.iter().zip(pools) {
+        match resolve_hop(h, pool.as_ref()) {
+            Some(hop) => hops.push(hop),
+            None => return search_optimum(cycle, pools),
+        }
+    }
+
+    let (a, b, c) = compose(&hops)?;
+
+    // a/b is the marginal rate at x = 0; no profit at any size if it's ≤ 1
+    if a <= b || c <= 0.0 {
+        return None;
+    }
+
+    let amount_in = ((a * b).sqrt() - b) / c;
+    if !amount_in.is_finite() || amount_in <= 0.0 {
+        return None;
+    }
+
+    let amount_out = a * amount_in / (b + c * amount_in);
+    let profit = amount_out - amount_in;
+    if !profit.is_finite() || profit <= 0.0 {
+        return None;
+    }
+
+    Some(CycleTrade {
+        amount_in,
+        amount_out,
+        profit,
+    })
+}
+
+/// Oriented reserves of the pool a cycle hop trades through, if it is
+/// a constant-product pool.
+fn resolve_hop(hop: &CycleHop, pool: &dyn AmmPool) -> Option<Hop> {
+    let (reserve_in, reserve_out) =
+        pool.constant_product_reserves(&hop.token_in, &hop.token_out)?;
+
+    Some(Hop {
+        reserve_in,
+        reserve_out,
+        fee: pool.fee(),
+    })
+}
+
+/// Fold hops into `(a, b, c)` with `out = a·x / (b + c·x)`.
+///
+/// Coefficients are renormalised so `b == 1` after every hop; otherwise
+/// raw 18-decimal reserves overflow f64 after a handful of hops.
+fn compose(hops: &[Hop]) -> Option<(f64, f64, f64)> {
+    // identity map: out = x
+    let (mut a, mut b, mut c) = (1.0_f64, 1.0_f64, 0.0_f64);
+
+    for h in hops {
+        let gamma = 1.0 - h.fee;
+        let na = a * gamma * h.reserve_out;
+        let nb = b * h.reserve_in;
+        let nc = c * h.reserve_in + gamma * a;
+
+        a = na / nb;
+        c = nc / nb;
+        b = 1.0;
+
+        if !(a.is_finite() && c.is_finite()) {
+            return None;
+        }
+    }
+
+    Some((a, b, c))
+}
+
+/// ------------------------------------------------------------
+/// Numeric optimum (golden-section search on exact quotes)
+/// ------------------------------------------------------------
+/// Profit along a cycle is concave in the input size for every AMM the
+/// engine models, so: double the input until profit turns down (or the
+/// quote fails), then golden-section search the last bracket.
+fn search_optimum(cycle: &ArbitrageCycle, pools: &[Arc<dyn AmmPool>]) -> Option<CycleTrade> {
+    let quote = |x: f64| -> Option<(f64, f64)> {
+        let amount_in = U256::try_from(x.floor()).ok()?;
+        let amount_out = *simulate_cycle(cycle, pools, amount_in)?.last()?;
+        Some((f64::from(amount_in), f64::from(amount_out)))
+    };
+    let profit = |x: f64| quote(x).map(|(i, o)| o - i).unwrap_or(f64::NEG_INFINITY);
+
+    // 1. bracket the peak on a doubling grid
+    let mut prev = f64::NEG_INFINITY;
+    let mut x = 1.0_f64;
+    let mut bracket = None;
+    for _ in 0..256 {
+        let p = profit(x);
+        if prev > 0.0 && p < prev {
+            bracket = Some((x / 4.0, x));
+            break;
+        }
+        prev = p;
+        x *= 2.0;
+    }
+    let (mut lo, mut hi) = bracket?;
+
+    // 2. golden-section search inside the bracket
+    const INV_PHI: f64 = 0.618_033_988_749_894_9;
+    let mut x1 = hi - INV_PHI * (hi - lo);
+    let mut x2 = lo + INV_PHI * (hi - lo);
+    let (mut p1, mut p2) = (profit(x1), profit(x2));
+    // stop at 1 wei or at f64 resolution, whichever is coarser
+    while hi - lo > (hi * 1e-12).max(1.0) {
+        if p1 < p2 {
+            lo = x1;
+            x1 = x2;
+            p1 = p2;
+            x2 = lo + INV_PHI * (hi - lo);
+            p2 = profit(x2);
+        } else {
+            hi = x2;
+            x2 = x1;
+            p2 = p1;
+            x1 = hi - INV_PHI * (hi - lo);
+            p1 = profit(x1);
+        }
+    }
+
+    let best = if p1 >= p2 { x1 } else { x2 };
+    let (amount_in, amount_out) = quote(best)?;
+    let profit = amount_out - amount_in;
+    if profit <= 0.0 {
+        return None;
+    }
+
+    Some(CycleTrade {
+        amount_in,
+        amount_out,
+        profit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Pool;
+    use crate::test_support::pair;
+
+    /// Cycle through `pools` in order, starting from `start`.
+    fn cycle(start: &str, pools: &[Pool]) -> ArbitrageCycle {
+        let mut path = vec![start.to_string()];
+        let mut hops = Vec::new();
+        for p in pools {
+            let token_in = path.last().unwrap().clone();
+            let zero_for_one = p.token0.id == token_in;
+            let token_out = if zero_for_one {
+                &p.token1.id
+            } else {
+                &p.token0.id
+            };
+            hops.push(CycleHop {
+                pool_id: p.id.clone(),
+                token_in,
+                token_out: token_out.clone(),
+                zero_for_one,
+                fee: p.fee(),
+                rate: 0.0,
+            });
+            path.push(token_out.clone());
+        }
+        ArbitrageCycle {
+            start_token: start.to_string(),
+            path,
+            hops,
+            product: 0.0,
+            profit_pct: 0.0,
+            amount_in: U256::ZERO,
+            amount_out: U256::ZERO,
+            profit: U256::ZERO,
+        }
+    }
+
+    fn as_amm(pools: Vec<Pool>) -> Vec<Arc<dyn AmmPool>> {
+        pools
+            .into_iter()
+            .map(|p| Arc::new(p) as Arc<dyn AmmPool>)
+            .collect()
+    }
+
+    /// Brute-force the same cycle hop by hop.
+    fn simulate(x: f64, hops: &[Hop]) -> f64 {
+        hops.iter().fold(x, |amt, h| {
+            let g = 1.0 - h.fee;
+            g * h.reserve_out * amt / (h.reserve_in + g * amt)
+        })
+    }
+
+    #[test]
+    fn optimum_beats_neighbouring_sizes() {
+        let pools = vec![
+            pair("p1", "a", "b", 1_000.0, 2_000.0),
+            pair("p2", "b", "c", 2_000.0, 3_000.0),
+            pair("p3", "c", "a", 2_700.0, 1_000.0),
+        ];
+        let c = cycle("a", &pools);
+        let any = as_amm(pools);
+        let trade = optimize_cycle(&c, &any).expect("profitable cycle");
+
+        let hops: Vec<Hop> = c
+            .hops
+            .iter()
+            .zip(&any)
+            .map(|(h, pool)| resolve_hop(h, pool.as_ref()).unwrap())
+            .collect();
+
+        let at_opt = simulate(trade.amount_in, &hops) - trade.amount_in;
+        assert!((at_opt - trade.profit).abs() < 1e-9);
+        for f in [0.5, 0.9, 1.1, 2.0] {
+            let x = trade.amount_in * f;
+            assert!(simulate(x, &hops) - x < trade.profit);
+        }
+    }
+
+    #[test]
+    fn numeric_search_agrees_with_closed_form() {
+        let e18 = 1e18;
+        let pools = vec![
+            pair("p1", "a", "b", 1_000.0 * e18, 2_000.0 * e18),
+            pair("p2", "b", "c", 2_000.0 * e18, 3_000.0 * e18),
+            pair("p3", "c", "a", 2_700.0 * e18, 1_000.0 * e18),
+        ];
+        let c = cycle("a", &pools);
+        let any = as_amm(pools);
+
+        let closed = optimize_cycle(&c, &any).unwrap();
+        let searched = search_optimum(&c, &any).unwrap();
+        assert!((searched.amount_in - closed.amount_in).abs() / closed.amount_in < 1e-3);
+        assert!((searched.profit - closed.profit).abs() / closed.profit < 1e-6);
+    }
+
+    #[test]
+    fn unprofitable_cycle_has_no_trade() {
+        let pools = vec![
+            pair("p1", "a", "b", 1_000.0, 1_000.0),
+            pair("p2", "b", "a", 1_000.0, 1_000.0),
+        ];
+        let c = cycle("a", &pools);
+        let any = as_amm(pools);
+        assert!(optimize_cycle(&c, &any).is_none());
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use crate::engine::{DEFAULT_BASE_TOKENS, Pool};
+use crate::pool::AmmPool;
+
+/// $1 tokens (lowercase `Token.id`): USDC, USDT, DAI
+pub const STABLECOINS: [&str; 3] = [
+    DEFAULT_BASE_TOKENS[1],
+    DEFAULT_BASE_TOKENS[2],
+    DEFAULT_BASE_TOKENS[3],
+];
+
+/// Uniswap V2 USDC/WETH, which prices WETH before anything else
+pub const USDC_WETH_PAIR: &str = "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc";
+
+/// A priced token only prices its neighbours through pools holding at
+/// least this much of it, so a dust pool can't set a price
+const MIN_PRICING_LIQUIDITY_USD: f64 = 1_000.0;
+
+/// -------------------------------
+/// USD price oracle
+/// -------------------------------
+/// USD per whole token, derived from V2 mid-prices: stablecoins are $1,
+/// the reference pair prices its other token (WETH), and every other
+/// token is priced, one hop further out each round, through its deepest
+/// pool against an already-priced token.
+#[derive(Debug, Clone, Default)]
+pub struct PriceOracle {
+    prices: HashMap<String, f64>,
+}
+
+impl PriceOracle {
+    /// Prices from `STABLECOINS` and `USDC_WETH_PAIR`
+    pub fn from_pools(pools: &[Pool]) -> Self {
+        Self::with_anchors(pools, &STABLECOINS, USDC_WETH_PAIR)
+    }
+
+    pub fn with_anchors(pools: &[Pool], stablecoins: &[&str], reference_pair: &str) -> Self {
+        let mut prices: HashMap<String, f64> =
+            stablecoins.iter().map(|s| (s.to_string(), 1.0)).collect();
+
+        // the reference pair is trusted whatever its depth
+        if let Some(pair) = pools.iter().find(|p| p.id == reference_pair)
+            && let Some((r0, r1)) = pair.whole_reserves()
+        {
+            match (prices.get(&pair.token0.id), prices.get(&pair.token1.id)) {
+                (Some(&p0), None) => {
+                    prices.insert(pair.token1.id.clone(), p0 * r0 / r1);
+                }
+                (None, Some(&p1)) => {
+                    prices.insert(pair.token0.id.clone(), p1 * r1 / r0);
+                }
+                _ => {}
+            }
+        }
+
+        loop {
+            // unpriced token -> (priced-side depth, price) of its deepest pool
+            let mut best: HashMap<&str, (f64, f64)> = HashMap::new();
+            for pool in pools {
+                let Some((r0, r1)) = pool.whole_reserves() else {
+                    continue;
+                };
+                for (known, r_known, other, r_other) in [
+                    (&pool.token0, r0, &pool.token1, r1),
+                    (&pool.token1, r1, &pool.token0, r0),
+                ] {
+                    if prices.contains_key(&other.id) {
+                        continue;
+                    }
+                    let Some(&known_price) = prices.get(&known.id) else {
+                        continue;
+                    };
+                    let depth = r_known * known_price;
+                    let price = depth / r_other;
+                    if depth < MIN_PRICING_LIQUIDITY_USD || !(price.is_finite() && price > 0.0) {
+                        continue;
+                    }
+                    match best.get(other.id.as_str()) {
+                        Some(&(deepest, _)) if deepest >= depth => {}
+                        _ => {
+                            best.insert(&other.id, (depth, price));
+                        }
+                    }
+                }
+            }
+            if best.is_empty() {
+                break;
+            }
+            prices.extend(best.into_iter().map(|(t, (_, p))| (t.to_string(), p)));
+        }
+
+        PriceOracle { prices }
+    }
+
+    /// USD per whole token
+    pub fn price(&self, token_id: &str) -> Option<f64> {
+        self.prices.get(token_id).copied()
+    }
+
+    /// Number of tokens with a price
+    pub fn len(&self) -> usize {
+        self.prices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty()
+    }
+
+    /// USD value of what a pool of any protocol holds of each token, in
+    /// `tokens()` order; `None` unless every token is priced and has
+    /// trusted decimals
+    pub fn holdings_usd(&self, pool: &dyn AmmPool) -> Option<Vec<f64>> {
+        let tokens = pool.tokens();
+        let holdings = pool.holdings()?;
+        if holdings.len() != tokens.len() || !tokens.iter().all(|t| t.decimals_trusted()) {
+            return None;
+        }
+        tokens
+            .iter()
+            .zip(holdings)
+            .map(|(t, amount)| Some(amount * self.price(&t.id)?))
+            .collect()
+    }
+
+    /// USD value of each side of the pool, `None` unless both are priced
+    pub fn side_liquidity_usd(&self, pool: &Pool) -> Option<(f64, f64)> {
+        match self.holdings_usd(pool)?[..] {
+            [usd0, usd1] => Some((usd0, usd1)),
+            _ => None,
+        }
+    }
+
+    /// Every side's USD value together
+    pub fn tvl_usd(&self, pool: &dyn AmmPool) -> Option<f64> {
+        self.holdings_usd(pool).map(|sides| sides.iter().sum())
+    }
+
+    /// Set `reserveUSD` on every pool the oracle can value; others keep
+    /// whatever they had (a subgraph figure, or none)
+    pub fn fill_reserve_usd(&self, pools: &mut [Pool]) {
+        for pool in pools {
+            if let Some(tvl) = self.tvl_usd(pool) {
+                pool.reserveUSD = Some(tvl.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ReserveUnits;
+    use crate::test_support::{pair, token_with_decimals};
+
+    /// Reserves in whole tokens
+    fn pool(id: &str, t0: (&str, u8), t1: (&str, u8), r0: f64, r1: f64) -> Pool {
+        Pool {
+            token0: token_with_decimals(t0.0, t0.1),
+            token1: token_with_decimals(t1.0, t1.1),
+            reserve_units: ReserveUnits::Scaled,
+            ..pair(id, t0.0, t1.0, r0, r1)
+        }
+    }
+
+    fn market() -> Vec<Pool> {
+        vec![
+            pool("ref", ("usdc", 6), ("weth", 18), 2_000_000.0, 1_000.0),
+            // deep: 1 LINK = 0.01 WETH = $20
+            pool("link-deep", ("link", 18), ("weth", 18), 50_000.0, 500.0),
+            // shallow and off-price: must not decide LINK's price
+            pool("link-shallow", ("link", 18), ("weth", 18), 10.0, 1.0),
+            // dust against WETH: $2 of WETH can't price DUST
+            pool("dust", ("dust", 18), ("weth", 18), 1.0, 0.001),
+            // two hops out: 1 UNI = 0.5 LINK = $10
+            pool("uni-link", ("uni", 18), ("link", 18), 2_000.0, 1_000.0),
+        ]
+    }
+
+    #[test]
+    fn walks_out_from_anchors_through_deepest_pools() {
+        let oracle = PriceOracle::with_anchors(&market(), &["usdc"], "ref");
+        assert_eq!(oracle.price("usdc"), Some(1.0));
+        assert!((oracle.price("weth").unwrap() - 2_000.0).abs() < 1e-9);
+        assert!((oracle.price("link").unwrap() - 20.0).abs() < 1e-9);
+        assert!((oracle.price("uni").unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(oracle.price("dust"), None);
+        assert_eq!(oracle.len(), 4);
+    }
+
+    #[test]
+    fn values_raw_and_scaled_reserves_alike() {
+        let mut pools = market();
+        let mut raw = pools[0].clone();
+        raw.reserve_units = ReserveUnits::Raw;
+        raw.reserve0 = "2000000000000".to_string();
+        raw.reserve1 = "1000000000000000000000".to_string();
+        pools[0] = raw;
+
+        let oracle = PriceOracle::with_anchors(&pools, &["usdc"], "ref");
+        let (usdc, weth) = oracle.side_liquidity_usd(&pools[0]).unwrap();
+        assert!((usdc - 2_000_000.0).abs() < 1e-6 && (weth - 2_000_000.0).abs() < 1e-6);
+
+        oracle.fill_reserve_usd(&mut pools);
+        let tvl: f64 = pools[1].reserveUSD.as_deref().unwrap().parse().unwrap();
+        assert!((tvl - 2_000_000.0).abs() < 1e-6);
+        assert_eq!(pools[3].reserveUSD, None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::Debug;
+
+use alloy::primitives::U256;
+
+use crate::engine::{DirEdge, Token};
+
+/// Which AMM family a pool belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    UniswapV2,
+    UniswapV3,
+    Curve,
+    Balancer,
+}
+
+/// -------------------------------
+/// Pool interface
+/// -------------------------------
+/// Everything the network builder, cycle search and optimizer need from a
+/// pool. A new DEX plugs in by implementing this; nothing in the engine
+/// matches on concrete pool types.
+///
+/// Token arguments are `Token.id`s. Amounts and rates are in raw token
+/// units (wei in, wei out).
+pub trait AmmPool: Debug + Send + Sync {
+    /// Pool address, as used in `DirEdge.pool_id`
+    fn id(&self) -> &str;
+
+    fn protocol(&self) -> Protocol;
+
+    /// Tradable tokens, in pool order (token0 first)
+    fn tokens(&self) -> Vec<&Token>;
+
+    /// Swap fee as a fraction (0.003 for 0.3%)
+    fn fee(&self) -> f64;
+
+    /// Small-trade rate token_in → token_out, fee included.
+    /// `None` if the pair isn't in the pool or the pool can't be priced.
+    fn spot_rate(&self, token_in: &str, token_out: &str) -> Option<f64>;
+
+    /// Exact output for an exact input, as the pool contract computes it.
+    /// `None` wherever the contract would revert.
+    fn quote(&self, amount_in: U256, token_in: &str, token_out: &str) -> Option<U256>;
+
+    /// `(reserve_in, reserve_out)` if the pool is a plain x*y=k pair, so the
+    /// optimizer can size trades in closed form instead of searching.
+    fn constant_product_reserves(&self, _token_in: &str, _token_out: &str) -> Option<(f64, f64)> {
+        None
+    }
+
+    /// Whole tokens the pool holds (or trades as if it held), in
+    /// `tokens()` order, for valuing it. `None` if it can't say.
+    fn holdings(&self) -> Option<Vec<f64>> {
+        None
+    }
+
+    /// USD liquidity as reported by the source the pool was listed from,
+    /// for when no oracle can value it
+    fn reported_tvl_usd(&self) -> Option<f64> {
+        None
+    }
+
+    /// One edge per ordered token pair with a usable spot rate, the rate
+    /// normalized by decimals to whole tokens out per whole token in, so
+    /// edge weights compare across pairs and protocols. None at all if any
+    /// token's decimals can't be trusted: its rates can't be normalized.
+    fn edges(&self) -> Vec<DirEdge> {
+        let tokens = self.tokens();
+        if !tokens.iter().all(|t| t.decimals_trusted()) {
+            return Vec::new();
+        }
+        let mut edges = Vec::new();
+        for (i, from) in tokens.iter().enumerate() {
+            for (j, to) in tokens.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+                let Some(raw_rate) = self.spot_rate(&from.id, &to.id) else {
+                    continue;
+                };
+                let rate = raw_rate * 10f64.powi(i32::from(from.decimals) - i32::from(to.decimals));
+                if !(rate.is_finite() && rate > 0.0) {
+                    continue;
+                }
+                edges.push(DirEdge {
+                    from: from.id.clone(),
+                    to: to.id.clone(),
+                    rate,
+                    weight: -rate.ln(),
+                    pool_id: self.id().to_string(),
+                    zero_for_one: i < j,
+                    fee: self.fee(),
+                });
+            }
+        }
+        edges
+    }
+}
+
+/// `raw` base units of `token` in whole tokens
+pub(crate) fn whole_tokens(raw: f64, token: &Token) -> f64 {
+    raw / 10f64.powi(token.decimals.into())
+}
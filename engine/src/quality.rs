@@ -0,0 +1,305 @@
+use crate::engine::Pool;
+use crate::oracle::PriceOracle;
+use crate::pool::AmmPool;
+
+/// -------------------------------
+/// Pool quality rules
+/// -------------------------------
+/// Thresholds a V2 pool has to meet before it enters the network. Dead
+/// and manipulated pairs quote prices nobody can trade at, and would
+/// otherwise crowd the results with phantom cycles.
+///
+/// Sync age is known in seconds (`blockTimestampLast`); `max_sync_blocks`
+/// counts it in blocks at one per 12-second slot, so missed slots make a
+/// pool look a little older than it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityRules {
+    pub min_raw_reserve: f64,         // either side, in base units
+    pub max_sync_age: u64,            // seconds since the pair's last Sync
+    pub max_sync_blocks: Option<u64>, // the same in blocks, if set
+    pub min_side_ratio: f64,          // smaller over larger USD side
+    pub min_raw_side_ratio: f64,      // the same in whole tokens, for unpriced pools
+    pub max_price_deviation: f64,     // from the oracle price: 0.25 = 25%
+}
+
+/// Seconds per slot: at most one block each
+const SLOT_SECONDS: u64 = 12;
+
+impl Default for QualityRules {
+    fn default() -> Self {
+        QualityRules {
+            min_raw_reserve: 1e6,
+            max_sync_age: 7 * 24 * 60 * 60,
+            max_sync_blocks: None,
+            min_side_ratio: 0.1,
+            // whole tokens of different prices: SHIB against WBTC is 1e-10
+            min_raw_side_ratio: 1e-12,
+            max_price_deviation: 0.25,
+        }
+    }
+}
+
+/// Why a pool was left out; the first rule it broke, in `RULES` order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exclusion {
+    /// A reserve is empty, unreadable, or below `min_raw_reserve`
+    MinReserve,
+    /// Reserves last synced `age` seconds before the snapshot
+    Stale { age: u64 },
+    /// Smaller side over the larger: USD value, or whole tokens if unpriced
+    Imbalanced { side_ratio: f64 },
+    /// Pool price over oracle price, less one
+    PriceDeviation { deviation: f64 },
+}
+
+/// Rule names, in the order pools are checked against them
+pub const RULES: [&str; 4] = ["min_reserve", "stale", "imbalance", "price_deviation"];
+
+impl Exclusion {
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Exclusion::MinReserve => RULES[0],
+            Exclusion::Stale { .. } => RULES[1],
+            Exclusion::Imbalanced { .. } => RULES[2],
+            Exclusion::PriceDeviation { .. } => RULES[3],
+        }
+    }
+}
+
+impl std::fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exclusion::MinReserve => write!(f, "reserve below minimum"),
+            Exclusion::Stale { age } => write!(
+                f,
+                "not synced for {} days (~{} blocks)",
+                age / 86_400,
+                age / SLOT_SECONDS
+            ),
+            Exclusion::Imbalanced { side_ratio } => {
+                write!(f, "sides imbalanced ({:.4} of each other)", side_ratio)
+            }
+            Exclusion::PriceDeviation { deviation } => {
+                write!(f, "price {:+.2}% off reference", deviation * 100.0)
+            }
+        }
+    }
+}
+
+/// A pool that was screened out, and why
+#[derive(Debug, Clone)]
+pub struct ExcludedPool {
+    pub pool_id: String,
+    pub reason: Exclusion,
+}
+
+/// Outcome of `screen_pools`
+#[derive(Debug, Clone, Default)]
+pub struct QualityReport {
+    pub excluded: Vec<ExcludedPool>,
+}
+
+impl QualityReport {
+    /// Pools removed by each rule, in `RULES` order
+    pub fn counts(&self) -> Vec<(&'static str, usize)> {
+        RULES
+            .iter()
+            .map(|&rule| {
+                let n = self
+                    .excluded
+                    .iter()
+                    .filter(|e| e.reason.rule() == rule)
+                    .count();
+                (rule, n)
+            })
+            .collect()
+    }
+}
+
+impl QualityRules {
+    /// The first rule `pool` breaks as of `timestamp` (unix seconds), if
+    /// any. Pools with no known last sync skip the age checks; pools
+    /// `oracle` can't value are balanced on whole tokens and skip the
+    /// price check.
+    pub fn check(&self, pool: &Pool, oracle: &PriceOracle, timestamp: u64) -> Option<Exclusion> {
+        let raw = pool.constant_product_reserves(&pool.token0.id, &pool.token1.id);
+        if !raw.is_some_and(|(r0, r1)| r0.min(r1) >= self.min_raw_reserve) {
+            return Some(Exclusion::MinReserve);
+        }
+
+        if let Some(synced_at) = pool.synced_at {
+            let age = timestamp.saturating_sub(synced_at);
+            let too_many_blocks = self
+                .max_sync_blocks
+                .is_some_and(|blocks| age / SLOT_SECONDS > blocks);
+            if age > self.max_sync_age || too_many_blocks {
+                return Some(Exclusion::Stale { age });
+            }
+        }
+
+        let usd = oracle.side_liquidity_usd(pool);
+        let (side0, side1, min_ratio) = match usd {
+            Some((usd0, usd1)) => (usd0, usd1, self.min_side_ratio),
+            None => {
+                let (whole0, whole1) = pool.whole_reserves()?;
+                (whole0, whole1, self.min_raw_side_ratio)
+            }
+        };
+        let side_ratio = side0.min(side1) / side0.max(side1);
+        if side_ratio < min_ratio {
+            return Some(Exclusion::Imbalanced { side_ratio });
+        }
+
+        let (usd0, usd1) = usd?;
+        // token1 priced in token0, the pool's own against the oracle's:
+        // (r0 / r1) / (p1 / p0) = usd0 / usd1
+        let deviation = usd0 / usd1 - 1.0;
+        if deviation.abs() > self.max_price_deviation {
+            return Some(Exclusion::PriceDeviation { deviation });
+        }
+        None
+    }
+}
+
+/// Drop every pool of `pools` that breaks one of `rules` as of
+/// `timestamp`, reporting each with its reason.
+pub fn screen_pools(
+    pools: &mut Vec<Pool>,
+    oracle: &PriceOracle,
+    timestamp: u64,
+    rules: &QualityRules,
+) -> QualityReport {
+    let mut report = QualityReport::default();
+    pools.retain(|pool| match rules.check(pool, oracle, timestamp) {
+        Some(reason) => {
+            report.excluded.push(ExcludedPool {
+                pool_id: pool.id.clone(),
+                reason,
+            });
+            false
+        }
+        None => true,
+    });
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{E18, pair, token, token_with_decimals};
+
+    const NOW: u64 = 1_700_000_000;
+    const DAY: u64 = 86_400;
+
+    fn pool(id: &str, r0: u128, r1: u128, synced_at: u64) -> Pool {
+        Pool {
+            block_number: Some(100),
+            synced_at: Some(synced_at),
+            ..pair(id, "usd", "eth", r0, r1)
+        }
+    }
+
+    #[test]
+    fn each_rule_reports_its_pools() {
+        let mut pools = vec![
+            // reference: 1 ETH = 2000 USD
+            pool("ref", 2_000_000 * E18, 1_000 * E18, NOW),
+            // quiet for a day, still fine
+            pool("fair", 200_000 * E18, 101 * E18, NOW - DAY),
+            pool("wei", 5, 1, NOW),
+            pool("dead", 200_000 * E18, 100 * E18, NOW - 30 * DAY),
+            // ETH at 2 USD: the sides are 1000x apart
+            pool("drained", 2_000 * E18, 1_000 * E18, NOW),
+            // ETH at 2600 USD
+            pool("skewed", 260_000 * E18, 100 * E18, NOW),
+        ];
+        let oracle = PriceOracle::with_anchors(&pools, &["usd"], "ref");
+        let report = screen_pools(&mut pools, &oracle, NOW, &QualityRules::default());
+
+        let kept: Vec<&str> = pools.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(kept, vec!["ref", "fair"]);
+        let reasons: Vec<(&str, &str)> = report
+            .excluded
+            .iter()
+            .map(|e| (e.pool_id.as_str(), e.reason.rule()))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("wei", "min_reserve"),
+                ("dead", "stale"),
+                ("drained", "imbalance"),
+                ("skewed", "price_deviation"),
+            ]
+        );
+        assert_eq!(
+            report.excluded[1].reason,
+            Exclusion::Stale { age: 30 * DAY }
+        );
+        match report.excluded[3].reason {
+            Exclusion::PriceDeviation { deviation } => assert!((deviation - 0.3).abs() < 1e-9),
+            ref other => panic!("{other:?}"),
+        }
+        assert_eq!(
+            report.counts(),
+            vec![
+                ("min_reserve", 1),
+                ("stale", 1),
+                ("imbalance", 1),
+                ("price_deviation", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn age_is_measured_from_the_last_sync_not_the_read() {
+        // read at the snapshot's own block, but untouched for a month
+        let mut lone = pool("lone", 5 * E18, 1, NOW - 30 * DAY);
+        lone.token0 = token("x");
+        lone.token1 = token("y");
+        let rules = QualityRules::default();
+        let oracle = PriceOracle::default();
+        assert_eq!(
+            rules.check(&lone, &oracle, NOW),
+            Some(Exclusion::MinReserve)
+        );
+
+        lone.reserve1 = (5 * E18).to_string();
+        assert_eq!(
+            rules.check(&lone, &oracle, NOW),
+            Some(Exclusion::Stale { age: 30 * DAY })
+        );
+        lone.synced_at = Some(NOW - DAY);
+        assert_eq!(rules.check(&lone, &oracle, NOW), None);
+        // a day is 7200 slots
+        let blocks = QualityRules {
+            max_sync_blocks: Some(7_000),
+            ..rules
+        };
+        assert_eq!(
+            blocks.check(&lone, &oracle, NOW),
+            Some(Exclusion::Stale { age: DAY })
+        );
+        // subgraph pools carry no sync time
+        lone.synced_at = None;
+        assert_eq!(rules.check(&lone, &oracle, NOW), None);
+    }
+
+    #[test]
+    fn unpriced_pools_are_balanced_on_whole_tokens() {
+        // a million X against 1e-12 Y: dust left on one side
+        let mut lone = pool("lone", 1_000_000 * E18, 1_000_000, NOW);
+        lone.token0 = token("x");
+        lone.token1 = token("y");
+        let rules = QualityRules::default();
+        let oracle = PriceOracle::default();
+        match rules.check(&lone, &oracle, NOW) {
+            Some(Exclusion::Imbalanced { side_ratio }) => assert!(side_ratio < 1e-17),
+            other => panic!("{other:?}"),
+        }
+
+        // the same reserve is one whole Y at 6 decimals
+        lone.token1 = token_with_decimals("y", 6);
+        assert_eq!(rules.check(&lone, &oracle, NOW), None);
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use alloy::primitives::U256;
+
+use crate::engine::ArbitrageCycle;
+use crate::optimizer::optimize_cycle;
+use crate::pool::AmmPool;
+
+/// V2 fees are in hundredths of a bip; Uniswap's 997/1000 is 3000 here,
+/// and forks' 9975/10000, 998/1000 etc. are exact too
+pub const FEE_DENOMINATOR: u32 = 1_000_000;
+
+/// Parse a raw on-chain reserve (base-10 integer string).
+///
+/// Subgraph reserves are decimal-scaled ("1234.5") and return `None`.
+pub fn parse_reserve(s: &str) -> Option<U256> {
+    U256::from_str_radix(s, 10).ok()
+}
+
+/// Port of `UniswapV2Library.getAmountOut`, at a pair fee of `fee`
+/// hundredths of a bip.
+///
+/// Returns `None` wherever the library would revert (zero input,
+/// zero liquidity) or on overflow.
+pub fn get_amount_out(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: u32,
+) -> Option<U256> {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return None;
+    }
+    let fee_numerator = FEE_DENOMINATOR.checked_sub(fee)?;
+    let amount_in_with_fee = amount_in.checked_mul(U256::from(fee_numerator))?;
+    let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+    let denominator = reserve_in
+        .checked_mul(U256::from(FEE_DENOMINATOR))?
+        .checked_add(amount_in_with_fee)?;
+    Some(numerator / denominator)
+}
+
+/// Port of `UniswapV2Library.getAmountIn` (rounds up by one wei).
+pub fn get_amount_in(
+    amount_out: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: u32,
+) -> Option<U256> {
+    if amount_out.is_zero() || reserve_in.is_zero() || amount_out >= reserve_out {
+        return None;
+    }
+    let fee_numerator = FEE_DENOMINATOR.checked_sub(fee)?;
+    let numerator = reserve_in
+        .checked_mul(amount_out)?
+        .checked_mul(U256::from(FEE_DENOMINATOR))?;
+    let denominator = (reserve_out - amount_out).checked_mul(U256::from(fee_numerator))?;
+    Some(numerator / denominator + U256::from(1))
+}
+
+/// Simulate a cycle hop by hop, like `UniswapV2Library.getAmountsOut`,
+/// quoting each hop on its own pool; `pools[i]` is the pool of
+/// `cycle.hops[i]` (see `engine::hop_pools`).
+///
+/// Returns the amount held after every hop, starting with `amount_in`.
+pub fn simulate_cycle(
+    cycle: &ArbitrageCycle,
+    pools: &[Arc<dyn AmmPool>],
+    amount_in: U256,
+) -> Option<Vec<U256>> {
+    let mut amounts = Vec::with_capacity(cycle.hops.len() + 1);
+    amounts.push(amount_in);
+
+    if pools.len() != cycle.hops.len() {
+        return None;
+    }
+    let mut amount = amount_in;
+    for (hop, pool) in cycle.hops.iter().zip(pools) {
+        amount = pool.quote(amount, &hop.token_in, &hop.token_out)?;
+        amounts.push(amount);
+    }
+    Some(amounts)
+}
+
+/// Size a cycle with the optimizer and re-price it in exact integer math.
+///
+/// Returns `(amount_in, amount_out)` only if the exact output beats the
+/// input, i.e. the pair contracts would actually pay out a profit.
+pub fn verify_cycle(cycle: &ArbitrageCycle, pools: &[Arc<dyn AmmPool>]) -> Option<(U256, U256)> {
+    let trade = optimize_cycle(cycle, pools)?;
+    let amount_in = U256::try_from(trade.amount_in.floor()).ok()?;
+    if amount_in.is_zero() {
+        return None;
+    }
+
+    let amounts = simulate_cycle(cycle, pools, amount_in)?;
+    let amount_out = *amounts.last()?;
+    if amount_out > amount_in {
+        Some((amount_in, amount_out))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::UNISWAP_V2_FEE;
+
+    fn u(v: u128) -> U256 {
+        U256::from(v)
+    }
+
+    #[test]
+    fn amount_out_matches_library() {
+        // 1 ETH into a 100 ETH / 200k USDC (6 decimals) pair
+        let out = get_amount_out(
+            u(10u128.pow(18)),
+            u(100 * 10u128.pow(18)),
+            u(200_000 * 10u128.pow(6)),
+            UNISWAP_V2_FEE,
+        );
+        // 997e18 * 2e11 / (1e23 + 997e18), floored
+        assert_eq!(out, Some(u(1_974_316_068)));
+    }
+
+    #[test]
+    fn amount_out_at_fork_fee() {
+        // PancakeSwap-style 0.25%: amountIn * 9975 / 10000
+        let out = get_amount_out(
+            u(10u128.pow(18)),
+            u(100 * 10u128.pow(18)),
+            u(200_000 * 10u128.pow(6)),
+            2500,
+        );
+        assert_eq!(out, Some(u(1_975_296_418)));
+    }
+
+    #[test]
+    fn amount_in_round_trips() {
+        let (r_in, r_out) = (u(5_000_000), u(9_000_000));
+        let want = u(12_345);
+        let needed = get_amount_in(want, r_in, r_out, UNISWAP_V2_FEE).unwrap();
+        assert!(get_amount_out(needed, r_in, r_out, UNISWAP_V2_FEE).unwrap() >= want);
+        assert!(get_amount_out(needed - u(1), r_in, r_out, UNISWAP_V2_FEE).unwrap() < want);
+    }
+
+    #[test]
+    fn reverts_become_none() {
+        assert_eq!(get_amount_out(U256::ZERO, u(1), u(1), UNISWAP_V2_FEE), None);
+        assert_eq!(get_amount_out(u(1), U256::ZERO, u(1), UNISWAP_V2_FEE), None);
+        assert_eq!(get_amount_in(u(10), u(100), u(10), UNISWAP_V2_FEE), None);
+        assert_eq!(parse_reserve("1234.5"), None);
+    }
+}
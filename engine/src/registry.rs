@@ -0,0 +1,494 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use alloy::primitives::Address;
+use dashmap::DashMap;
+use eyre::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::classify::Verdict;
+use crate::engine::{DecimalsStatus, Pool, ReserveUnits, Token};
+use crate::uniswap_v2::V2Factory;
+
+/// Default registry file, next to the working directory
+pub const REGISTRY_PATH: &str = "registry.sqlite";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS factories (
+        address      TEXT PRIMARY KEY,
+        name         TEXT NOT NULL,
+        pairs_listed INTEGER NOT NULL  -- next allPairs index to list
+    );
+    CREATE TABLE IF NOT EXISTS tokens (
+        address         TEXT PRIMARY KEY,
+        symbol          TEXT NOT NULL,
+        name            TEXT NOT NULL,
+        decimals        INTEGER NOT NULL,
+        decimals_status TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pairs (
+        address      TEXT PRIMARY KEY,
+        factory      TEXT NOT NULL,
+        pair_index   INTEGER NOT NULL,
+        token0       TEXT,
+        token1       TEXT,
+        reserve0     TEXT,
+        reserve1     TEXT,
+        fee          INTEGER,
+        block_number INTEGER, -- block the reserves were read at
+        synced_at    INTEGER  -- getReserves' blockTimestampLast
+    );
+    CREATE INDEX IF NOT EXISTS pairs_by_factory ON pairs (factory);
+    CREATE TABLE IF NOT EXISTS token_verdicts (
+        address      TEXT PRIMARY KEY,
+        verdict      TEXT NOT NULL,    -- `classify::Verdict`, as JSON
+        block_number INTEGER NOT NULL  -- fork block it was simulated at
+    );
+    CREATE TABLE IF NOT EXISTS tracked_pairs (
+        address      TEXT PRIMARY KEY,
+        block_number INTEGER NOT NULL  -- discovery run that kept it
+    );
+";
+
+/// -------------------------------
+/// Pool registry
+/// -------------------------------
+/// What earlier runs already learned, in SQLite: each factory's pair list
+/// (and how far it was listed), token metadata and honeypot verdicts, and
+/// the last reserves read for each pair with their block. A fresh run lists only pairs
+/// created since and re-reads only reserves that have gone stale; runs
+/// without discovery re-read only the pairs the last one tracked.
+pub struct Registry {
+    conn: Mutex<Connection>,
+}
+
+impl Registry {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// A registry that lives only as long as the process
+    pub fn in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        // registries written before `synced_at` was recorded
+        let has_synced_at = conn
+            .prepare("SELECT 1 FROM pragma_table_info('pairs') WHERE name = 'synced_at'")?
+            .exists([])?;
+        if !has_synced_at {
+            conn.execute_batch("ALTER TABLE pairs ADD COLUMN synced_at INTEGER")?;
+        }
+        Ok(Registry {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// How many of the factory's `allPairs` have been listed (0 if never)
+    pub fn pairs_listed(&self, dex: &V2Factory) -> Result<usize> {
+        let listed: Option<i64> = self
+            .conn()
+            .query_row(
+                "SELECT pairs_listed FROM factories WHERE address = ?1",
+                [format!("{:#x}", dex.factory)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(listed.unwrap_or(0) as usize)
+    }
+
+    /// Record `allPairs[start..start + pairs.len()]` and move the factory's
+    /// listing mark past them.
+    pub fn record_pairs(&self, dex: &V2Factory, start: usize, pairs: &[Address]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let factory = format!("{:#x}", dex.factory);
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR IGNORE INTO pairs (address, factory, pair_index) VALUES (?1, ?2, ?3)",
+            )?;
+            for (i, pair) in pairs.iter().enumerate() {
+                insert.execute(params![format!("{:#x}", pair), factory, (start + i) as i64])?;
+            }
+        }
+        tx.execute(
+            "INSERT INTO factories (address, name, pairs_listed) VALUES (?1, ?2, ?3)
+             ON CONFLICT (address) DO UPDATE SET name = ?2, pairs_listed = MAX(pairs_listed, ?3)",
+            params![factory, dex.name, (start + pairs.len()) as i64],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Pairs whose reserves are missing or were read more than `max_age`
+    /// blocks before `block_number`, in listing order
+    pub fn stale_pairs(
+        &self,
+        dex: &V2Factory,
+        block_number: u64,
+        max_age: u64,
+    ) -> Result<Vec<Address>> {
+        let oldest = block_number.saturating_sub(max_age) as i64;
+        let conn = self.conn();
+        let mut query = conn.prepare(
+            "SELECT address FROM pairs
+             WHERE factory = ?1 AND (block_number IS NULL OR block_number < ?2)
+             ORDER BY pair_index",
+        )?;
+        let rows = query.query_map(params![format!("{:#x}", dex.factory), oldest], |row| {
+            row.get::<_, String>(0)
+        })?;
+        let mut stale = Vec::new();
+        for address in rows {
+            stale.push(address?.parse()?);
+        }
+        Ok(stale)
+    }
+
+    /// Every pair of the factory with known reserves and token metadata,
+    /// as last recorded
+    pub fn load_pools(&self, dex: &V2Factory) -> Result<Vec<Pool>> {
+        let conn = self.conn();
+        let mut query = conn.prepare(
+            "SELECT p.address, p.reserve0, p.reserve1, p.fee, p.block_number, p.synced_at,
+                    t0.address, t0.symbol, t0.name, t0.decimals, t0.decimals_status,
+                    t1.address, t1.symbol, t1.name, t1.decimals, t1.decimals_status
+             FROM pairs p
+             JOIN tokens t0 ON t0.address = p.token0
+             JOIN tokens t1 ON t1.address = p.token1
+             WHERE p.factory = ?1 AND p.reserve0 IS NOT NULL
+             ORDER BY p.pair_index",
+        )?;
+        let rows = query.query_map([format!("{:#x}", dex.factory)], |row| {
+            Ok(Pool {
+                id: row.get(0)?,
+                reserve0: row.get(1)?,
+                reserve1: row.get(2)?,
+                reserve_units: ReserveUnits::Raw,
+                reserveUSD: None,
+                fee: row.get(3)?,
+                dex: dex.name.to_string(),
+                block_number: row.get::<_, Option<i64>>(4)?.map(|b| b as u64),
+                synced_at: row.get::<_, Option<i64>>(5)?.map(|t| t as u64),
+                token0: token_from_row(row, 6)?,
+                token1: token_from_row(row, 11)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Store freshly read pairs: tokens, raw reserves, fee, block and last sync
+    pub fn save_pools(&self, pools: &[Pool]) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        {
+            let mut update = tx.prepare(
+                "UPDATE pairs SET token0 = ?2, token1 = ?3, reserve0 = ?4, reserve1 = ?5,
+                                  fee = ?6, block_number = ?7, synced_at = ?8
+                 WHERE address = ?1",
+            )?;
+            for p in pools {
+                update.execute(params![
+                    p.id,
+                    p.token0.id,
+                    p.token1.id,
+                    p.reserve0,
+                    p.reserve1,
+                    p.fee,
+                    p.block_number.map(|b| b as i64),
+                    p.synced_at.map(|t| t as i64),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Fill `cache` with every recorded token
+    pub fn load_tokens(&self, cache: &DashMap<Address, Token>) -> Result<()> {
+        let conn = self.conn();
+        let mut query =
+            conn.prepare("SELECT address, symbol, name, decimals, decimals_status FROM tokens")?;
+        let rows = query.query_map([], |row| token_from_row(row, 0))?;
+        for token in rows {
+            let token = token?;
+            cache.insert(token.id.parse()?, token);
+        }
+        Ok(())
+    }
+
+    /// Record every token in `cache`
+    pub fn save_tokens(&self, cache: &DashMap<Address, Token>) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT OR REPLACE INTO tokens (address, symbol, name, decimals, decimals_status)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for entry in cache.iter() {
+                let t = entry.value();
+                upsert.execute(params![
+                    format!("{:#x}", entry.key()),
+                    t.symbol,
+                    t.name,
+                    t.decimals,
+                    decimals_status_name(t.decimals_status),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replace the tracked pairs with `pools`: what a discovery run kept
+    /// after screening, at `block_number`
+    pub fn track_pairs(&self, pools: &[Pool], block_number: u64) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM tracked_pairs", [])?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR IGNORE INTO tracked_pairs (address, block_number) VALUES (?1, ?2)",
+            )?;
+            for p in pools {
+                insert.execute(params![p.id, block_number as i64])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The factory's tracked pairs
+    pub fn tracked_pairs(&self, dex: &V2Factory) -> Result<HashSet<Address>> {
+        let conn = self.conn();
+        let mut query = conn.prepare(
+            "SELECT t.address FROM tracked_pairs t
+             JOIN pairs p ON p.address = t.address
+             WHERE p.factory = ?1",
+        )?;
+        let rows = query.query_map([format!("{:#x}", dex.factory)], |row| {
+            row.get::<_, String>(0)
+        })?;
+        let mut tracked = HashSet::new();
+        for address in rows {
+            tracked.insert(address?.parse()?);
+        }
+        Ok(tracked)
+    }
+
+    /// How many pairs are tracked, over every factory (0 before the first
+    /// discovery run)
+    pub fn tracked_pair_count(&self) -> Result<usize> {
+        let count: i64 =
+            self.conn()
+                .query_row("SELECT COUNT(*) FROM tracked_pairs", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    /// Every cached honeypot verdict, by token id
+    pub fn load_verdicts(&self) -> Result<HashMap<String, Verdict>> {
+        let conn = self.conn();
+        let mut query = conn.prepare("SELECT address, verdict FROM token_verdicts")?;
+        let rows = query.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut verdicts = HashMap::new();
+        for row in rows {
+            let (address, verdict) = row?;
+            verdicts.insert(address, serde_json::from_str(&verdict)?);
+        }
+        Ok(verdicts)
+    }
+
+    /// Record verdicts simulated on a fork of `block_number`, replacing
+    /// older ones for the same tokens
+    pub fn save_verdicts(&self, verdicts: &[(String, Verdict)], block_number: u64) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT OR REPLACE INTO token_verdicts (address, verdict, block_number)
+                 VALUES (?1, ?2, ?3)",
+            )?;
+            for (token, verdict) in verdicts {
+                upsert.execute(params![
+                    token,
+                    serde_json::to_string(verdict)?,
+                    block_number as i64
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Token from five columns starting at `first`:
+/// address, symbol, name, decimals, decimals_status
+fn token_from_row(row: &rusqlite::Row<'_>, first: usize) -> rusqlite::Result<Token> {
+    let status: String = row.get(first + 4)?;
+    Ok(Token {
+        id: row.get(first)?,
+        symbol: row.get(first + 1)?,
+        name: row.get(first + 2)?,
+        decimals: row.get(first + 3)?,
+        decimals_status: match status.as_str() {
+            "missing" => DecimalsStatus::Missing,
+            "implausible" => DecimalsStatus::Implausible,
+            _ => DecimalsStatus::Reported,
+        },
+    })
+}
+
+fn decimals_status_name(status: DecimalsStatus) -> &'static str {
+    match status {
+        DecimalsStatus::Reported => "reported",
+        DecimalsStatus::Missing => "missing",
+        DecimalsStatus::Implausible => "implausible",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+    use crate::test_support::{self, pair};
+    use crate::uniswap_v2::UNISWAP_V2;
+
+    fn token(id: Address, status: DecimalsStatus) -> Token {
+        Token {
+            decimals_status: status,
+            ..test_support::token(&format!("{:#x}", id))
+        }
+    }
+
+    #[test]
+    fn pools_round_trip_and_go_stale() {
+        let registry = Registry::in_memory().unwrap();
+        let a = address!("00000000000000000000000000000000000000aa");
+        let b = address!("00000000000000000000000000000000000000bb");
+        let fresh = address!("0000000000000000000000000000000000000001");
+        let old = address!("0000000000000000000000000000000000000002");
+
+        assert_eq!(registry.pairs_listed(&UNISWAP_V2).unwrap(), 0);
+        registry
+            .record_pairs(&UNISWAP_V2, 0, &[fresh, old])
+            .unwrap();
+        assert_eq!(registry.pairs_listed(&UNISWAP_V2).unwrap(), 2);
+        // never read: both stale
+        assert_eq!(
+            registry.stale_pairs(&UNISWAP_V2, 100, 10).unwrap(),
+            vec![fresh, old]
+        );
+
+        let cache = DashMap::new();
+        cache.insert(a, token(a, DecimalsStatus::Reported));
+        cache.insert(b, token(b, DecimalsStatus::Missing));
+        registry.save_tokens(&cache).unwrap();
+
+        let pool = |id: Address, block_number: u64| Pool {
+            token1: token(b, DecimalsStatus::Missing),
+            block_number: Some(block_number),
+            synced_at: Some(1_700_000_000),
+            ..pair(
+                &format!("{:#x}", id),
+                &format!("{:#x}", a),
+                &format!("{:#x}", b),
+                1000,
+                2000,
+            )
+        };
+        registry
+            .save_pools(&[pool(fresh, 95), pool(old, 80)])
+            .unwrap();
+        assert_eq!(
+            registry.stale_pairs(&UNISWAP_V2, 100, 10).unwrap(),
+            vec![old]
+        );
+
+        let loaded = registry.load_pools(&UNISWAP_V2).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].id, format!("{:#x}", fresh));
+        assert_eq!(loaded[0].reserve1, "2000");
+        assert_eq!(loaded[0].block_number, Some(95));
+        assert_eq!(loaded[0].synced_at, Some(1_700_000_000));
+        assert_eq!(loaded[0].token1.decimals_status, DecimalsStatus::Missing);
+
+        let reloaded = DashMap::new();
+        registry.load_tokens(&reloaded).unwrap();
+        assert_eq!(reloaded.len(), 2);
+    }
+
+    #[test]
+    fn verdicts_are_cached() {
+        let registry = Registry::in_memory().unwrap();
+        assert!(registry.load_verdicts().unwrap().is_empty());
+
+        let pot = (
+            "0xpot".to_string(),
+            Verdict::Failed("sell reverted".to_string()),
+        );
+        let taxed = (
+            "0xtax".to_string(),
+            Verdict::Passed {
+                buy_tax: 0.05,
+                sell_tax: 0.05,
+            },
+        );
+        registry.save_verdicts(&[pot.clone(), taxed], 100).unwrap();
+        let retaxed = (
+            "0xtax".to_string(),
+            Verdict::Passed {
+                buy_tax: 0.0,
+                sell_tax: 0.0,
+            },
+        );
+        registry.save_verdicts(&[retaxed.clone()], 200).unwrap();
+
+        let verdicts = registry.load_verdicts().unwrap();
+        assert_eq!(verdicts.len(), 2);
+        assert_eq!(verdicts[&pot.0], pot.1);
+        assert_eq!(verdicts[&retaxed.0], retaxed.1);
+    }
+
+    #[test]
+    fn listing_mark_only_moves_forward() {
+        let registry = Registry::in_memory().unwrap();
+        let pair = address!("0000000000000000000000000000000000000001");
+        registry
+            .record_pairs(&UNISWAP_V2, 0, &[pair, pair])
+            .unwrap();
+        registry.record_pairs(&UNISWAP_V2, 0, &[pair]).unwrap();
+        assert_eq!(registry.pairs_listed(&UNISWAP_V2).unwrap(), 2);
+    }
+
+    #[test]
+    fn tracking_replaces_the_previous_set() {
+        let registry = Registry::in_memory().unwrap();
+        let (kept, dropped) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        registry
+            .record_pairs(&UNISWAP_V2, 0, &[kept, dropped])
+            .unwrap();
+        let pool = |id: Address| pair(&format!("{:#x}", id), "a", "b", 1000, 2000);
+        assert_eq!(registry.tracked_pair_count().unwrap(), 0);
+
+        registry
+            .track_pairs(&[pool(kept), pool(dropped)], 10)
+            .unwrap();
+        registry.track_pairs(&[pool(kept)], 20).unwrap();
+        assert_eq!(registry.tracked_pair_count().unwrap(), 1);
+        assert_eq!(
+            registry.tracked_pairs(&UNISWAP_V2).unwrap(),
+            HashSet::from([kept])
+        );
+    }
+}
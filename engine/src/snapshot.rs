@@ -0,0 +1,401 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use eyre::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::balancer::BalancerPool;
+use crate::classify::TokenClassifier;
+use crate::curve::CurvePool;
+use crate::datafetcher::SkippedPool;
+use crate::engine::{LiquidityFilter, Network, Pool, add_pools, construct_network};
+use crate::oracle::PriceOracle;
+use crate::pool::AmmPool;
+use crate::quality::{QualityReport, QualityRules, screen_pools};
+use crate::uniswap_v3::V3Pool;
+
+/// -------------------------------
+/// Market snapshot
+/// -------------------------------
+/// Every pool as of one block, so cycles are never priced across pools
+/// read at different heights.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    pub block_number: u64,
+    pub timestamp: u64, // block timestamp, unix seconds
+    pub v2_pools: Vec<Pool>,
+    pub v3_pools: Vec<V3Pool>,
+    pub curve_pools: Vec<CurvePool>,
+    pub balancer_pools: Vec<BalancerPool>,
+    #[serde(skip)] // diagnostics of the fetch, not market state
+    pub skipped: Vec<SkippedPool>, // V2 pairs that failed to load
+}
+
+impl MarketSnapshot {
+    /// Token graph over every pool in the snapshot.
+    pub fn network(&self) -> Network {
+        let mut network = construct_network(&self.v2_pools);
+        add_pools(&mut network, self.v3_pools.iter().cloned());
+        add_pools(&mut network, self.curve_pools.iter().cloned());
+        add_pools(&mut network, self.balancer_pools.iter().cloned());
+        network
+    }
+
+    /// Drop the V2 pools that break one of `rules` at the snapshot's block
+    /// time, priced from the snapshot's own pools.
+    pub fn screen(&mut self, rules: &QualityRules) -> QualityReport {
+        let oracle = PriceOracle::from_pools(&self.v2_pools);
+        screen_pools(&mut self.v2_pools, &oracle, self.timestamp, rules)
+    }
+
+    /// Drop every pool, of any protocol, that trades a token `classifier`
+    /// won't route through. Returns how many were dropped.
+    pub fn retain_tradable(&mut self, classifier: &TokenClassifier) -> usize {
+        let tradable =
+            |pool: &dyn AmmPool| pool.tokens().iter().all(|t| classifier.tradable(&t.id));
+        let before = self.pool_count();
+        self.v2_pools.retain(|p| tradable(p));
+        self.v3_pools.retain(|p| tradable(p));
+        self.curve_pools.retain(|p| tradable(p));
+        self.balancer_pools.retain(|p| tradable(p));
+        before - self.pool_count()
+    }
+
+    /// Every token traded by a pool of the snapshot
+    pub fn token_ids(&self) -> HashSet<&str> {
+        let v2 = self.v2_pools.iter().map(|p| p as &dyn AmmPool);
+        let v3 = self.v3_pools.iter().map(|p| p as &dyn AmmPool);
+        let curve = self.curve_pools.iter().map(|p| p as &dyn AmmPool);
+        let balancer = self.balancer_pools.iter().map(|p| p as &dyn AmmPool);
+        v2.chain(v3)
+            .chain(curve)
+            .chain(balancer)
+            .flat_map(|p| p.tokens())
+            .map(|t| t.id.as_str())
+            .collect()
+    }
+
+    fn pool_count(&self) -> usize {
+        self.v2_pools.len()
+            + self.v3_pools.len()
+            + self.curve_pools.len()
+            + self.balancer_pools.len()
+    }
+
+    /// Drop every pool, of any protocol, that `filter` rejects at prices
+    /// derived from the snapshot's V2 pools, and fill `reserveUSD` on the
+    /// V2 pools kept. Returns how many were dropped.
+    pub fn retain_liquid(&mut self, filter: &LiquidityFilter) -> usize {
+        let oracle = PriceOracle::from_pools(&self.v2_pools);
+        let before = self.pool_count();
+        self.v2_pools.retain(|p| filter.keeps(p, &oracle));
+        self.v3_pools.retain(|p| filter.keeps(p, &oracle));
+        self.curve_pools.retain(|p| filter.keeps(p, &oracle));
+        self.balancer_pools.retain(|p| filter.keeps(p, &oracle));
+        oracle.fill_reserve_usd(&mut self.v2_pools);
+        before - self.pool_count()
+    }
+}
+
+/// -------------------------------
+/// Snapshot files
+/// -------------------------------
+/// Bumped whenever a field of `MarketSnapshot` or a pool type changes
+/// shape; older files are rejected rather than misread.
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+/// Leads every binary snapshot, ahead of the version
+const BINARY_MAGIC: &[u8; 4] = b"ARBS";
+
+/// On-disk encoding, picked from the file extension by `save_snapshot`
+/// and `load_snapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// `.json`: readable, diffable test fixtures
+    Json,
+    /// anything else: `ARBS`, u32 LE version, then bincode
+    Binary,
+}
+
+impl SnapshotFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => SnapshotFormat::Json,
+            _ => SnapshotFormat::Binary,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotFileRef<'a> {
+    version: u32,
+    snapshot: &'a MarketSnapshot,
+}
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct SnapshotFile {
+    snapshot: MarketSnapshot,
+}
+
+/// Write `snapshot` to `path` (format from the extension).
+pub fn save_snapshot(snapshot: &MarketSnapshot, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let mut out = BufWriter::new(File::create(path)?);
+    write_snapshot(snapshot, SnapshotFormat::from_path(path), &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Read a snapshot written by `save_snapshot`.
+pub fn load_snapshot(path: impl AsRef<Path>) -> Result<MarketSnapshot> {
+    let path = path.as_ref();
+    let input = BufReader::new(File::open(path)?);
+    read_snapshot(SnapshotFormat::from_path(path), input)
+}
+
+pub fn write_snapshot(
+    snapshot: &MarketSnapshot,
+    format: SnapshotFormat,
+    mut out: impl Write,
+) -> Result<()> {
+    match format {
+        SnapshotFormat::Json => {
+            let file = SnapshotFileRef {
+                version: SNAPSHOT_VERSION,
+                snapshot,
+            };
+            serde_json::to_writer_pretty(out, &file)?;
+        }
+        SnapshotFormat::Binary => {
+            out.write_all(BINARY_MAGIC)?;
+            out.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+            bincode::serialize_into(out, snapshot)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_snapshot(format: SnapshotFormat, mut input: impl Read) -> Result<MarketSnapshot> {
+    match format {
+        SnapshotFormat::Json => {
+            // version first, so a newer layout fails on the version and not
+            // on whichever field moved
+            let mut json = String::new();
+            input.read_to_string(&mut json)?;
+            let header: SnapshotHeader = serde_json::from_str(&json)?;
+            check_version(header.version)?;
+            let file: SnapshotFile = serde_json::from_str(&json)?;
+            Ok(file.snapshot)
+        }
+        SnapshotFormat::Binary => {
+            let mut header = [0u8; 8];
+            input.read_exact(&mut header)?;
+            if &header[..4] != BINARY_MAGIC {
+                bail!("not a binary market snapshot");
+            }
+            check_version(u32::from_le_bytes(header[4..].try_into()?))?;
+            Ok(bincode::deserialize_from(input)?)
+        }
+    }
+}
+
+fn check_version(version: u32) -> Result<()> {
+    if version != SNAPSHOT_VERSION {
+        bail!(
+            "snapshot version {} is not supported (expected {})",
+            version,
+            SNAPSHOT_VERSION
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use alloy::primitives::U256;
+
+    use super::*;
+    use crate::curve::FeeOrder;
+    use crate::engine::{DEFAULT_BASE_TOKENS, DecimalsStatus, Token, find_arbitrage};
+    use crate::oracle::USDC_WETH_PAIR;
+    use crate::test_support::{E18, pair, token, v3_pool};
+
+    fn missing_decimals(id: &str) -> Token {
+        Token {
+            decimals_status: DecimalsStatus::Missing,
+            ..token(id)
+        }
+    }
+
+    fn v2(id: &str, r0: u128, r1: u128) -> Pool {
+        Pool {
+            block_number: Some(7),
+            ..pair(id, "a", "b", r0, r1)
+        }
+    }
+
+    /// A parallel-pool arbitrage, a V3 pool with ticks, and a pair whose
+    /// token decimals are unknown
+    fn fixture() -> MarketSnapshot {
+        let v3 = V3Pool {
+            tick_bitmap: HashMap::from([(-1, U256::from(1) << 255), (0, U256::from(2))]),
+            ticks: BTreeMap::from([(-60, 10i128.pow(20)), (60, -(10i128.pow(20)))]),
+            ..v3_pool("v3", "a", "b", 10u128.pow(20))
+        };
+        MarketSnapshot {
+            block_number: 7,
+            timestamp: 1_700_000_000,
+            v2_pools: vec![
+                v2("uni", 1_000 * E18, 2_000 * E18),
+                v2("sushi", 1_000 * E18, 1_800 * E18),
+                Pool {
+                    token1: missing_decimals("c"),
+                    ..v2("unknown", E18, E18)
+                },
+            ],
+            v3_pools: vec![v3],
+            curve_pools: vec![],
+            balancer_pools: vec![],
+            skipped: vec![],
+        }
+    }
+
+    fn round_trip(format: SnapshotFormat) -> MarketSnapshot {
+        let mut bytes = Vec::new();
+        write_snapshot(&fixture(), format, &mut bytes).unwrap();
+        read_snapshot(format, bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        for format in [SnapshotFormat::Json, SnapshotFormat::Binary] {
+            let loaded = round_trip(format);
+            assert_eq!((loaded.block_number, loaded.timestamp), (7, 1_700_000_000));
+            assert_eq!(loaded.v2_pools[1].reserve1, fixture().v2_pools[1].reserve1);
+            assert_eq!(
+                loaded.v2_pools[2].token1.decimals_status,
+                DecimalsStatus::Missing
+            );
+            assert_eq!(loaded.v3_pools[0].ticks, fixture().v3_pools[0].ticks);
+            assert_eq!(
+                loaded.v3_pools[0].tick_bitmap,
+                fixture().v3_pools[0].tick_bitmap
+            );
+
+            // same graph, same opportunity
+            let found = find_arbitrage(&loaded.network(), 0.0);
+            let expected = find_arbitrage(&fixture().network(), 0.0);
+            assert_eq!(found.len(), expected.len());
+            assert_eq!(found[0].profit, expected[0].profit);
+        }
+    }
+
+    #[test]
+    fn drops_illiquid_pools_of_every_protocol() {
+        let (weth, usdc, dai) = (
+            DEFAULT_BASE_TOKENS[0],
+            DEFAULT_BASE_TOKENS[1],
+            DEFAULT_BASE_TOKENS[3],
+        );
+        let curve = |id: &str, units: u128| CurvePool {
+            id: id.to_string(),
+            tokens: vec![token(usdc), token(dai)],
+            balances: vec![U256::from(units * E18); 2],
+            rates: vec![U256::from(E18); 2],
+            amp: U256::from(100),
+            a_precision: U256::from(1),
+            fee: U256::from(4_000_000),
+            base_pool: None,
+            fee_order: FeeOrder::CoinUnits,
+        };
+        // a real USDC/WETH spread across two pairs, a dust pair whose
+        // mid-price alone would be a 1000% cycle, and dust V3 and Curve
+        // pools next to deep ones
+        let market = MarketSnapshot {
+            v2_pools: vec![
+                pair(USDC_WETH_PAIR, usdc, weth, 2_000_000 * E18, 1_000 * E18),
+                pair("usdc-weth-2", usdc, weth, 1_990_000 * E18, 1_000 * E18),
+                pair(
+                    "dust",
+                    usdc,
+                    weth,
+                    20_000 * E18 / 1_000_000,
+                    E18 / 1_000_000,
+                ),
+            ],
+            // at tick 0: L/1e18 whole tokens of each, ~$2001 per 1e18 of L
+            v3_pools: vec![
+                v3_pool("v3", usdc, weth, 1_000 * E18),
+                v3_pool("v3-dust", usdc, weth, E18 / 1_000),
+            ],
+            curve_pools: vec![curve("curve", 1_000_000), curve("curve-dust", 1)],
+            ..fixture()
+        };
+        let ids = |snapshot: &MarketSnapshot| {
+            let mut ids: Vec<String> = snapshot
+                .network()
+                .pools
+                .iter()
+                .map(|p| p.id().to_string())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        let mut snapshot = market.clone();
+        assert_eq!(snapshot.retain_liquid(&LiquidityFilter::default()), 3);
+        assert_eq!(
+            ids(&snapshot),
+            [USDC_WETH_PAIR, "curve", "usdc-weth-2", "v3"]
+        );
+        let tvl: f64 = snapshot.v2_pools[0]
+            .reserveUSD
+            .as_deref()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((tvl - 4_000_000.0).abs() < 1e-3);
+
+        // per-side: only the reference pair holds $1.995M of both
+        let strict = LiquidityFilter {
+            min_tvl_usd: 0.0,
+            min_side_usd: 1_995_000.0,
+        };
+        let mut snapshot = market;
+        assert_eq!(snapshot.retain_liquid(&strict), 6);
+        assert_eq!(ids(&snapshot), [USDC_WETH_PAIR]);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = Vec::new();
+        write_snapshot(&fixture(), SnapshotFormat::Binary, &mut bytes).unwrap();
+        bytes[4] = 99;
+        assert!(read_snapshot(SnapshotFormat::Binary, bytes.as_slice()).is_err());
+
+        let json = r#"{ "version": 99, "snapshot": { "layout": "from the future" } }"#;
+        let err = read_snapshot(SnapshotFormat::Json, json.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("version 99"));
+    }
+
+    #[test]
+    fn format_follows_extension() {
+        assert_eq!(
+            SnapshotFormat::from_path(Path::new("block.json")),
+            SnapshotFormat::Json
+        );
+        assert_eq!(
+            SnapshotFormat::from_path(Path::new("block.snap")),
+            SnapshotFormat::Binary
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use alloy::primitives::Address;
+use dashmap::DashMap;
+use eyre::{Result, bail, eyre};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::datafetcher::{PoolFetch, PoolSource};
+use crate::engine::{Pool, Token};
+use crate::uniswap_v2::UNISWAP_V2;
+
+/// Uniswap V2 on The Graph's decentralized network
+pub const UNISWAP_V2_SUBGRAPH: &str =
+    "https://gateway.thegraph.com/api/subgraphs/id/A3Np3RQbaBA6oKJgiwDJeo5T3zrYfGHPWFYayMwtNDum";
+
+/// The Graph caps `first` at 1000
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// One page of pairs after `$cursor`, by id. Paging on `id_gt` rather
+/// than `skip` keeps every page as cheap as the first.
+const PAIRS_QUERY: &str = r#"
+query Pairs($first: Int!, $cursor: ID!, $minReserveUSD: BigDecimal!) {
+  pairs(
+    first: $first,
+    orderBy: id,
+    orderDirection: asc,
+    where: {
+      id_gt: $cursor,
+      reserveUSD_gte: $minReserveUSD,
+      token0_: { derivedETH_gt: 0 },
+      token1_: { derivedETH_gt: 0 }
+    }
+  ) {
+    id
+    reserveUSD
+    reserve0
+    reserve1
+    token0 { id symbol name decimals }
+    token1 { id symbol name decimals }
+  }
+}
+"#;
+
+/// -------------------------------
+/// GraphQL response models
+/// -------------------------------
+#[derive(Debug, Deserialize)]
+struct GraphQLResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Option<Vec<GraphQLError>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLError {
+    message: String,
+    #[serde(default)]
+    locations: Option<Vec<GraphQLErrorLocation>>,
+    #[serde(default)]
+    path: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLErrorLocation {
+    line: u32,
+    column: u32,
+}
+
+impl std::fmt::Display for GraphQLError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(path) = self.path.as_ref().filter(|p| !p.is_empty()) {
+            let path: Vec<String> = path
+                .iter()
+                .map(|p| p.as_str().map_or_else(|| p.to_string(), str::to_string))
+                .collect();
+            write!(f, " at {}", path.join("."))?;
+        }
+        if let Some(loc) = self.locations.as_ref().and_then(|l| l.first()) {
+            write!(f, " (line {}, column {})", loc.line, loc.column)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PairsPage {
+    pairs: Vec<Pool>,
+}
+
+/// -------------------------------
+/// Subgraph configuration
+/// -------------------------------
+/// Which subgraph to list pairs from, and which of them to keep. From the
+/// environment: `ARB_SUBGRAPH_URL`, `GRAPH_API_KEY`, `ARB_SUBGRAPH_DEX`,
+/// `ARB_SUBGRAPH_MIN_USD`, `ARB_SUBGRAPH_MAX_POOLS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubgraphConfig {
+    pub endpoint: String,
+    pub api_key: Option<String>, // sent as a bearer token
+    pub dex: String,             // `V2Factory.name` the subgraph indexes
+    pub page_size: usize,
+    pub min_reserve_usd: f64, // pairs below this TVL aren't listed
+    pub max_pools: Option<usize>,
+}
+
+impl Default for SubgraphConfig {
+    fn default() -> Self {
+        SubgraphConfig {
+            endpoint: UNISWAP_V2_SUBGRAPH.to_string(),
+            api_key: None,
+            dex: UNISWAP_V2.name.to_string(),
+            page_size: MAX_PAGE_SIZE,
+            min_reserve_usd: 0.0,
+            max_pools: None,
+        }
+    }
+}
+
+impl SubgraphConfig {
+    pub fn load() -> Result<Self> {
+        Self::with_overrides(|key| std::env::var(key).ok())
+    }
+
+    /// Defaults, with `ARB_SUBGRAPH_*` / `GRAPH_API_KEY` (looked up through
+    /// `var`) applied over them
+    fn with_overrides(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut config = SubgraphConfig::default();
+        if let Some(url) = var("ARB_SUBGRAPH_URL") {
+            config.endpoint = url;
+        }
+        config.api_key = var("GRAPH_API_KEY").filter(|k| !k.is_empty());
+        if let Some(dex) = var("ARB_SUBGRAPH_DEX") {
+            config.dex = dex;
+        }
+        if let Some(usd) = var("ARB_SUBGRAPH_MIN_USD") {
+            config.min_reserve_usd = usd
+                .parse()
+                .map_err(|e| eyre!("ARB_SUBGRAPH_MIN_USD: {}", e))?;
+        }
+        if let Some(max) = var("ARB_SUBGRAPH_MAX_POOLS") {
+            config.max_pools = Some(
+                max.parse()
+                    .map_err(|e| eyre!("ARB_SUBGRAPH_MAX_POOLS: {}", e))?,
+            );
+        }
+        Ok(config)
+    }
+}
+
+/// -------------------------------
+/// Subgraph pool source
+/// -------------------------------
+/// Lists V2 pairs from a subgraph: thousands of pools in a few requests,
+/// but as of the subgraph's last indexed block and with scaled reserves.
+/// Wrap it in `datafetcher::Verified` to re-read them on-chain.
+pub struct SubgraphSource {
+    config: SubgraphConfig,
+    client: reqwest::Client,
+}
+
+impl SubgraphSource {
+    pub fn new(config: SubgraphConfig) -> Self {
+        SubgraphSource {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Every pair above `min_reserve_usd`, page by page, up to `max_pools`,
+    /// tagged with the configured DEX
+    pub async fn fetch_pairs(&self) -> Result<Vec<Pool>> {
+        let page_size = self.config.page_size.clamp(1, MAX_PAGE_SIZE);
+        let mut pools: Vec<Pool> = Vec::new();
+        let mut cursor = String::new();
+        loop {
+            let first = match self.config.max_pools {
+                Some(max) if max <= pools.len() => break,
+                Some(max) => page_size.min(max - pools.len()),
+                None => page_size,
+            };
+            let page = self.fetch_page(first, &cursor).await?;
+            let full = page.len() == first;
+            if let Some(last) = page.last() {
+                cursor = last.id.clone();
+            }
+            pools.extend(page.into_iter().map(|mut p| {
+                p.dex = self.config.dex.clone();
+                p
+            }));
+            println!("subgraph: {} pairs listed", pools.len());
+            if !full {
+                break;
+            }
+        }
+        Ok(pools)
+    }
+
+    async fn fetch_page(&self, first: usize, cursor: &str) -> Result<Vec<Pool>> {
+        let body = json!({
+            "query": PAIRS_QUERY,
+            "variables": page_variables(first, cursor, self.config.min_reserve_usd),
+        });
+        let mut request = self.client.post(&self.config.endpoint).json(&body);
+        if let Some(key) = &self.config.api_key {
+            request = request.bearer_auth(key);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            bail!("subgraph returned {}: {}", status, text);
+        }
+        decode_page(&text)
+    }
+}
+
+impl PoolSource for SubgraphSource {
+    fn name(&self) -> &str {
+        "subgraph"
+    }
+
+    /// Pairs as the subgraph last indexed them; `block_number` is not
+    /// used, and metadata comes with the pairs rather than `token_cache`
+    async fn fetch_pools(
+        &self,
+        _block_number: u64,
+        _token_cache: Arc<DashMap<Address, Token>>,
+    ) -> Result<PoolFetch> {
+        Ok(PoolFetch {
+            pools: self.fetch_pairs().await?,
+            skipped: Vec::new(),
+        })
+    }
+}
+
+fn page_variables(first: usize, cursor: &str, min_reserve_usd: f64) -> serde_json::Value {
+    json!({
+        "first": first,
+        "cursor": cursor,
+        // BigDecimal travels as a string
+        "minReserveUSD": min_reserve_usd.to_string(),
+    })
+}
+
+/// Pairs of one response, or every GraphQL error it carries
+fn decode_page(body: &str) -> Result<Vec<Pool>> {
+    let response: GraphQLResponse<PairsPage> = serde_json::from_str(body)?;
+    if let Some(errors) = response.errors.filter(|e| !e.is_empty()) {
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        bail!("subgraph query failed: {}", messages.join("; "));
+    }
+    let page = response
+        .data
+        .ok_or_else(|| eyre!("subgraph response had no `data` field"))?;
+    Ok(page.pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::engine::ReserveUnits;
+
+    #[test]
+    fn decodes_pairs_and_surfaces_errors() {
+        let body = r#"{ "data": { "pairs": [
+            { "id": "0xpair", "reserveUSD": "400000.5", "reserve0": "200000.25", "reserve1": "100",
+              "token0": { "id": "0xusdc", "symbol": "USDC", "name": "USD Coin", "decimals": "6" },
+              "token1": { "id": "0xweth", "symbol": "WETH", "name": "Wrapped Ether", "decimals": "18" } }
+        ] } }"#;
+        let pairs = decode_page(body).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].reserveUSD.as_deref(), Some("400000.5"));
+        assert_eq!(pairs[0].reserve_units, ReserveUnits::Scaled);
+        assert_eq!(pairs[0].token0.decimals, 6);
+
+        let body = r#"{ "data": null, "errors": [
+            { "message": "Type `Pair` has no field `reserveUSDD`",
+              "locations": [{ "line": 3, "column": 5 }], "path": ["pairs", 0] }
+        ] }"#;
+        let err = decode_page(body).unwrap_err().to_string();
+        assert!(err.contains("no field `reserveUSDD`"), "{err}");
+        assert!(err.contains("at pairs.0 (line 3, column 5)"), "{err}");
+
+        assert!(decode_page(r#"{ "data": null }"#).is_err());
+    }
+
+    #[test]
+    fn pages_by_id_cursor() {
+        let vars = page_variables(1000, "0xabc", 10_000.0);
+        assert_eq!(vars["first"], 1000);
+        assert_eq!(vars["cursor"], "0xabc");
+        assert_eq!(vars["minReserveUSD"], "10000");
+        assert!(PAIRS_QUERY.contains("id_gt: $cursor"));
+    }
+
+    #[test]
+    fn configured_from_environment() {
+        let map: HashMap<&str, &str> = HashMap::from([
+            (
+                "ARB_SUBGRAPH_URL",
+                "http://localhost:8000/subgraphs/name/sushi",
+            ),
+            ("GRAPH_API_KEY", "secret"),
+            ("ARB_SUBGRAPH_DEX", "sushiswap"),
+            ("ARB_SUBGRAPH_MIN_USD", "25000"),
+        ]);
+        let config = SubgraphConfig::with_overrides(|k| map.get(k).map(|v| v.to_string())).unwrap();
+        assert_eq!(
+            config.endpoint,
+            "http://localhost:8000/subgraphs/name/sushi"
+        );
+        assert_eq!(config.api_key.as_deref(), Some("secret"));
+        assert_eq!(config.dex, "sushiswap");
+        assert_eq!(config.min_reserve_usd, 25_000.0);
+        assert_eq!(config.max_pools, None);
+
+        let defaults = SubgraphConfig::with_overrides(|_| None).unwrap();
+        assert_eq!(defaults, SubgraphConfig::default());
+        assert!(
+            SubgraphConfig::with_overrides(
+                |k| (k == "ARB_SUBGRAPH_MAX_POOLS").then(|| "lots".to_string())
+            )
+            .is_err()
+        );
+    }
+}
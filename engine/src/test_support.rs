@@ -0,0 +1,65 @@
+use std::collections::{BTreeMap, HashMap};
+
+use alloy::primitives::U256;
+
+use crate::engine::{DecimalsStatus, Pool, ReserveUnits, Token, UNISWAP_V2_FEE};
+use crate::uniswap_v3::{V3Pool, sqrt_ratio_at_tick};
+
+/// One whole 18-decimal token, in base units
+pub(crate) const E18: u128 = 1_000_000_000_000_000_000;
+
+/// 18-decimal token whose id, name and (uppercased) symbol are `id`
+pub(crate) fn token(id: &str) -> Token {
+    token_with_decimals(id, 18)
+}
+
+pub(crate) fn token_with_decimals(id: &str, decimals: u8) -> Token {
+    Token {
+        symbol: id.to_uppercase(),
+        name: id.to_string(),
+        id: id.to_string(),
+        decimals,
+        decimals_status: DecimalsStatus::Reported,
+    }
+}
+
+/// Uniswap V2 pair of two `token`s with raw reserves, never read on-chain;
+/// tests override whatever else they need with `..pair(..)`
+pub(crate) fn pair(
+    id: &str,
+    token0: &str,
+    token1: &str,
+    reserve0: impl ToString,
+    reserve1: impl ToString,
+) -> Pool {
+    Pool {
+        id: id.to_string(),
+        token0: token(token0),
+        token1: token(token1),
+        reserve0: reserve0.to_string(),
+        reserve1: reserve1.to_string(),
+        reserve_units: ReserveUnits::Raw,
+        reserveUSD: None,
+        fee: UNISWAP_V2_FEE,
+        dex: "uniswap_v2".to_string(),
+        block_number: None,
+        synced_at: None,
+    }
+}
+
+/// 0.3% V3 pool at tick 0 with `liquidity` in range and no initialized
+/// ticks in the two bitmap words around it
+pub(crate) fn v3_pool(id: &str, token0: &str, token1: &str, liquidity: u128) -> V3Pool {
+    V3Pool {
+        id: id.to_string(),
+        token0: token(token0),
+        token1: token(token1),
+        fee: 3000,
+        tick_spacing: 60,
+        sqrt_price_x96: sqrt_ratio_at_tick(0).unwrap(),
+        tick: 0,
+        liquidity,
+        tick_bitmap: HashMap::from([(-1, U256::ZERO), (0, U256::ZERO)]),
+        ticks: BTreeMap::new(),
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+
+use alloy::primitives::{Address, B256, U256, address, b256, keccak256};
+use eyre::{Result, bail, eyre};
+use serde::Deserialize;
+
+use crate::engine::{Pool, ReserveUnits, Token, UNISWAP_V2_FEE};
+use crate::pool::{AmmPool, Protocol};
+use crate::quote::{FEE_DENOMINATOR, get_amount_out, parse_reserve};
+
+/// Fee config file read by `FactoryFees::load` when `ARB_FACTORY_FEES`
+/// isn't set
+pub const FACTORY_FEES_PATH: &str = "factory_fees.json";
+
+/// Where a V2 factory's pair fee comes from (hundredths of a bip)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum V2Fee {
+    /// One fee for every pair (3000 = 0.3%, 2500 = 0.25%, ...)
+    Fixed(u32),
+    /// Per-pair `swapFee()` over `denominator` (Biswap: 1000), with
+    /// `fallback` for pairs that don't answer
+    PairSwapFee { denominator: u32, fallback: u32 },
+}
+
+/// -------------------------------
+/// V2-compatible DEXes
+/// -------------------------------
+/// A Uniswap V2 fork: where to list its pairs, how to derive a pair
+/// address, and which router executes against it.
+#[derive(Debug, Clone, Copy)]
+pub struct V2Factory {
+    pub name: &'static str, // tag carried by every `Pool` from this factory
+    pub factory: Address,
+    pub router: Address,
+    pub init_code_hash: B256, // pair creation code hash, for CREATE2
+    pub fee: V2Fee,
+}
+
+pub const UNISWAP_V2: V2Factory = V2Factory {
+    name: "uniswap_v2",
+    factory: address!("5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f"),
+    router: address!("7a250d5630B4cF539739dF2C5dAcb4c659F2488D"),
+    init_code_hash: b256!("96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f"),
+    fee: V2Fee::Fixed(UNISWAP_V2_FEE),
+};
+
+pub const SUSHISWAP: V2Factory = V2Factory {
+    name: "sushiswap",
+    factory: address!("C0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"),
+    router: address!("d9e1cE17f2641f24aE83637ab66a2cca9C378B9F"),
+    init_code_hash: b256!("e18a34eb0e04b04f7a0ac29a6e80748dca96319b42c54d679cb821dca90c6303"),
+    fee: V2Fee::Fixed(3000),
+};
+
+pub const PANCAKESWAP_V2: V2Factory = V2Factory {
+    name: "pancakeswap_v2",
+    factory: address!("1097053Fd2ea711dad45caCcc45EfF7548fCB362"),
+    router: address!("EfF92A263d31888d860bD50809A8D171709b7b1c"),
+    init_code_hash: b256!("57224589c67f3f30a6b0d7a1b54cf3153ab84563bc609ef41dfb34f8b2974d2d"),
+    fee: V2Fee::Fixed(2500),
+};
+
+pub const SHIBASWAP: V2Factory = V2Factory {
+    name: "shibaswap",
+    factory: address!("115934131916C8b277DD010Ee02de363c09d037c"),
+    router: address!("03f7724180AA6b939894B5Ca4314783B0b36b329"),
+    init_code_hash: b256!("65d1a3b1e46c6e4f1be1ad5f99ef14dc488ae0549dc97db9b30afe2241ce1c7a"),
+    fee: V2Fee::Fixed(3000),
+};
+
+/// Factories `data_fetcher` lists pairs from
+pub const V2_FACTORIES: [V2Factory; 4] = [UNISWAP_V2, SUSHISWAP, PANCAKESWAP_V2, SHIBASWAP];
+
+/// Look up a factory by the tag on its pools (`Pool.dex`).
+pub fn v2_factory(name: &str) -> Option<&'static V2Factory> {
+    V2_FACTORIES.iter().find(|f| f.name == name)
+}
+
+/// -------------------------------
+/// Factory fee config
+/// -------------------------------
+/// Fees that replace the compiled-in ones, by `V2Factory.name`:
+///
+/// ```json
+/// { "sushiswap": { "fixed": 2500 },
+///   "pancakeswap_v2": { "pair_swap_fee": { "denominator": 10000, "fallback": 2500 } } }
+/// ```
+///
+/// `fixed` is in hundredths of a bip; `pair_swap_fee` reads each pair's
+/// `swapFee()` over `denominator`, `fallback` for pairs that don't answer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct FactoryFees(pub HashMap<String, V2Fee>);
+
+impl FactoryFees {
+    /// `ARB_FACTORY_FEES` (or `factory_fees.json` if present); no
+    /// overrides if neither exists
+    pub fn load() -> Result<Self> {
+        match env::var("ARB_FACTORY_FEES") {
+            Ok(path) => Self::from_file(path),
+            Err(_) if Path::new(FACTORY_FEES_PATH).exists() => Self::from_file(FACTORY_FEES_PATH),
+            Err(_) => Ok(FactoryFees::default()),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("reading {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// `factories` with their configured fees; fails on a name that
+    /// matches none of them, so a typo can't silently keep the default
+    pub fn apply(&self, factories: &[V2Factory]) -> Result<Vec<V2Factory>> {
+        if let Some(unknown) = self
+            .0
+            .keys()
+            .find(|n| !factories.iter().any(|f| f.name == n.as_str()))
+        {
+            bail!("fee configured for unknown factory {:?}", unknown);
+        }
+        Ok(factories
+            .iter()
+            .map(|f| V2Factory {
+                fee: self.0.get(f.name).copied().unwrap_or(f.fee),
+                ..*f
+            })
+            .collect())
+    }
+}
+
+impl V2Factory {
+    /// `UniswapV2Library.pairFor`: the CREATE2 address of the a/b pair,
+    /// whether or not it has been deployed.
+    pub fn pair_address(&self, token_a: Address, token_b: Address) -> Address {
+        let (token0, token1) = if token_a < token_b {
+            (token_a, token_b)
+        } else {
+            (token_b, token_a)
+        };
+        let mut packed = [0u8; 40];
+        packed[..20].copy_from_slice(token0.as_slice());
+        packed[20..].copy_from_slice(token1.as_slice());
+        self.factory.create2(keccak256(packed), self.init_code_hash)
+    }
+}
+
+impl Pool {
+    /// Order `(r0, r1)` as `(reserve_in, reserve_out)` for a trade
+    /// token_in → token_out; `None` if that isn't this pair.
+    fn orient<T>(&self, token_in: &str, token_out: &str, r0: T, r1: T) -> Option<(T, T)> {
+        if token_in == self.token0.id && token_out == self.token1.id {
+            Some((r0, r1))
+        } else if token_in == self.token1.id && token_out == self.token0.id {
+            Some((r1, r0))
+        } else {
+            None
+        }
+    }
+
+    /// Reserves in whole tokens; `None` if they don't parse, are empty, or
+    /// are raw with a token whose decimals can't be trusted
+    pub fn whole_reserves(&self) -> Option<(f64, f64)> {
+        let mut r0: f64 = self.reserve0.parse().ok()?;
+        let mut r1: f64 = self.reserve1.parse().ok()?;
+        if self.reserve_units == ReserveUnits::Raw {
+            if !(self.token0.decimals_trusted() && self.token1.decimals_trusted()) {
+                return None;
+            }
+            r0 /= 10f64.powi(self.token0.decimals.into());
+            r1 /= 10f64.powi(self.token1.decimals.into());
+        }
+        (r0 > 0.0 && r1 > 0.0).then_some((r0, r1))
+    }
+}
+
+impl AmmPool for Pool {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::UniswapV2
+    }
+
+    fn tokens(&self) -> Vec<&Token> {
+        vec![&self.token0, &self.token1]
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee as f64 / FEE_DENOMINATOR as f64
+    }
+
+    fn holdings(&self) -> Option<Vec<f64>> {
+        let (r0, r1) = self.whole_reserves()?;
+        Some(vec![r0, r1])
+    }
+
+    /// The subgraph's `reserveUSD`, when listed from one
+    fn reported_tvl_usd(&self) -> Option<f64> {
+        self.reserveUSD.as_deref()?.parse().ok()
+    }
+
+    /// Mid-price R_out / R_in, less the fee
+    fn spot_rate(&self, token_in: &str, token_out: &str) -> Option<f64> {
+        let (reserve_in, reserve_out) = self.constant_product_reserves(token_in, token_out)?;
+        let rate = (1.0 - self.fee()) * reserve_out / reserve_in;
+        (rate.is_finite() && rate > 0.0).then_some(rate)
+    }
+
+    /// `getAmountOut` at this pair's fee, on raw reserves; `None` for
+    /// subgraph-scaled ones
+    fn quote(&self, amount_in: U256, token_in: &str, token_out: &str) -> Option<U256> {
+        if self.reserve_units != ReserveUnits::Raw {
+            return None;
+        }
+        let r0 = parse_reserve(&self.reserve0)?;
+        let r1 = parse_reserve(&self.reserve1)?;
+        let (reserve_in, reserve_out) = self.orient(token_in, token_out, r0, r1)?;
+        get_amount_out(amount_in, reserve_in, reserve_out, self.fee)
+    }
+
+    /// Reserves as raw-unit floats; subgraph (scaled) ones are multiplied
+    /// back up by 10^decimals
+    fn constant_product_reserves(&self, token_in: &str, token_out: &str) -> Option<(f64, f64)> {
+        let mut r0: f64 = self.reserve0.parse().ok()?;
+        let mut r1: f64 = self.reserve1.parse().ok()?;
+        if self.reserve_units == ReserveUnits::Scaled {
+            r0 *= 10f64.powi(self.token0.decimals.into());
+            r1 *= 10f64.powi(self.token1.decimals.into());
+        }
+        if !(r0 > 0.0 && r1 > 0.0) {
+            return None;
+        }
+        self.orient(token_in, token_out, r0, r1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::DecimalsStatus;
+    use crate::test_support::{pair, token, token_with_decimals};
+
+    /// 100 WETH / 200k USDC, in the given units
+    fn usdc_weth(reserve_units: ReserveUnits) -> Pool {
+        let (usdc, weth) = match reserve_units {
+            ReserveUnits::Raw => ("200000000000", "100000000000000000000"),
+            ReserveUnits::Scaled => ("200000", "100"),
+        };
+        Pool {
+            token0: token_with_decimals("usdc", 6),
+            reserve_units,
+            ..pair("usdc-weth", "usdc", "weth", usdc, weth)
+        }
+    }
+
+    #[test]
+    fn configured_fees_replace_the_defaults() {
+        let fees: FactoryFees = serde_json::from_str(
+            r#"{ "sushiswap": { "fixed": 2500 },
+                 "shibaswap": { "pair_swap_fee": { "denominator": 1000, "fallback": 3000 } } }"#,
+        )
+        .unwrap();
+        let factories = fees.apply(&V2_FACTORIES).unwrap();
+        assert_eq!(factories[0].fee, V2Fee::Fixed(UNISWAP_V2_FEE));
+        assert_eq!(factories[1].fee, V2Fee::Fixed(2500));
+        assert_eq!(
+            factories[3].fee,
+            V2Fee::PairSwapFee {
+                denominator: 1000,
+                fallback: 3000
+            }
+        );
+
+        let typo: FactoryFees = serde_json::from_str(r#"{ "sushi": { "fixed": 2500 } }"#).unwrap();
+        assert!(typo.apply(&V2_FACTORIES).is_err());
+    }
+
+    #[test]
+    fn raw_and_scaled_reserves_give_the_same_edges() {
+        for units in [ReserveUnits::Raw, ReserveUnits::Scaled] {
+            let edges = usdc_weth(units).edges();
+            let weth_to_usdc = edges.iter().find(|e| e.from == "weth").unwrap();
+            let usdc_to_weth = edges.iter().find(|e| e.from == "usdc").unwrap();
+            // ~2000 USDC per WETH, not 2000e-12
+            assert!(
+                (weth_to_usdc.rate - 2_000.0 * 0.997).abs() < 1e-6,
+                "{units:?}"
+            );
+            assert!(
+                (usdc_to_weth.rate - 0.997 / 2_000.0).abs() < 1e-12,
+                "{units:?}"
+            );
+        }
+        // only raw reserves can be quoted exactly
+        let amount = U256::from(10u64.pow(18));
+        assert!(
+            usdc_weth(ReserveUnits::Raw)
+                .quote(amount, "weth", "usdc")
+                .is_some()
+        );
+        assert!(
+            usdc_weth(ReserveUnits::Scaled)
+                .quote(amount, "weth", "usdc")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn untrusted_decimals_give_no_edges() {
+        for status in [DecimalsStatus::Missing, DecimalsStatus::Implausible] {
+            let pool = Pool {
+                token1: Token {
+                    decimals_status: status,
+                    ..token("weth")
+                },
+                ..usdc_weth(ReserveUnits::Scaled)
+            };
+            assert!(pool.edges().is_empty(), "{status:?}");
+        }
+    }
+
+    #[test]
+    fn pair_address_matches_deployed_pair() {
+        let weth = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let usdc = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        assert_eq!(
+            UNISWAP_V2.pair_address(usdc, weth),
+            address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")
+        );
+        assert_eq!(
+            SUSHISWAP.pair_address(weth, usdc),
+            address!("397FF1542f962076d0BFE58eA045FfA2d347ACa0")
+        );
+    }
+
+    #[test]
+    fn factories_are_found_by_pool_tag() {
+        for f in &V2_FACTORIES {
+            assert_eq!(v2_factory(f.name).unwrap().factory, f.factory);
+        }
+        assert!(v2_factory("unknown").is_none());
+    }
+}
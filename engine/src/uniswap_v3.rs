@@ -0,0 +1,806 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use alloy::{
+    eips::BlockId,
+    primitives::{
+        Address, U256, U512,
+        aliases::{I24, U24},
+        ruint::UintTryFrom,
+    },
+    providers::Provider,
+    sol,
+    sol_types::SolCall,
+};
+use dashmap::DashMap;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::datafetcher::{ADDR_BATCH, Call, IMulticall2, MULTICALL2, load_token, load_tokens};
+use crate::engine::Token;
+use crate::pool::{AmmPool, Protocol, whole_tokens};
+
+sol! {
+    #[sol(rpc)]
+    interface IUniswapV3Factory {
+        function getPool(address tokenA, address tokenB, uint24 fee) returns (address);
+    }
+
+    #[sol(rpc)]
+    interface IUniswapV3Pool {
+        function token0() returns (address);
+        function token1() returns (address);
+        function fee() returns (uint24);
+        function tickSpacing() returns (int24);
+        function liquidity() returns (uint128);
+        function slot0()
+            returns (
+                uint160 sqrtPriceX96,
+                int24 tick,
+                uint16 observationIndex,
+                uint16 observationCardinality,
+                uint16 observationCardinalityNext,
+                uint8 feeProtocol,
+                bool unlocked
+            );
+        function tickBitmap(int16 wordPosition) returns (uint256);
+        function ticks(int24 tick)
+            returns (
+                uint128 liquidityGross,
+                int128 liquidityNet,
+                uint256 feeGrowthOutside0X128,
+                uint256 feeGrowthOutside1X128,
+                int56 tickCumulativeOutside,
+                uint160 secondsPerLiquidityOutsideX128,
+                uint32 secondsOutside,
+                bool initialized
+            );
+    }
+}
+
+pub const UNISWAP_V3_FACTORY: Address =
+    alloy::primitives::address!("1F98431c8aD98523631AE4a59f672588e8c2e6B5");
+/// Fee tiers enabled on the V3 factory, in hundredths of a bip
+pub const V3_FEE_TIERS: [u32; 4] = [100, 500, 3000, 10000];
+/// Bitmap words loaded on each side of the current tick; swaps that would
+/// walk past them are treated as unquotable rather than guessed
+const BITMAP_WORDS_EACH_SIDE: i16 = 2;
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+const MIN_SQRT_RATIO: U256 = U256::from_limbs([4295128739, 0, 0, 0]);
+const MAX_SQRT_RATIO: U256 =
+    U256::from_limbs([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0]);
+const FEE_PIPS_DENOMINATOR: u32 = 1_000_000;
+
+/// -------------------------------
+/// Pool model
+/// -------------------------------
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct V3Pool {
+    pub id: String,
+    pub token0: Token,
+    pub token1: Token,
+    pub fee: u32, // hundredths of a bip: 500 = 0.05%
+    pub tick_spacing: i32,
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+    pub tick_bitmap: HashMap<i16, U256>, // loaded words only
+    pub ticks: BTreeMap<i32, i128>,      // initialized tick -> liquidityNet
+}
+
+impl V3Pool {
+    /// Fee as a fraction (0.0005 for the 5 bps tier)
+    pub fn fee_fraction(&self) -> f64 {
+        self.fee as f64 / FEE_PIPS_DENOMINATOR as f64
+    }
+
+    /// Mid-price token0 → token1 in raw units: (sqrtPriceX96 / 2^96)^2
+    pub fn mid_price_0to1(&self) -> f64 {
+        let sqrt = f64::from(self.sqrt_price_x96) / 2f64.powi(96);
+        sqrt * sqrt
+    }
+
+    /// Mirror a `Mint` (positive `delta`) or `Burn` (negative) on
+    /// [tick_lower, tick_upper). Only ticks in loaded bitmap words are
+    /// tracked; swaps never cross past those anyway.
+    pub fn modify_position(&mut self, tick_lower: i32, tick_upper: i32, delta: i128) {
+        for (tick, net) in [(tick_lower, delta), (tick_upper, -delta)] {
+            let (word, bit) = bitmap_position(tick / self.tick_spacing);
+            let Some(bits) = self.tick_bitmap.get_mut(&word) else {
+                continue;
+            };
+            let liquidity_net = self.ticks.entry(tick).or_insert(0);
+            *liquidity_net += net;
+            if *liquidity_net == 0 {
+                // fully withdrawn: the tick is no longer initialized
+                self.ticks.remove(&tick);
+                *bits &= !(U256::from(1) << bit);
+            } else {
+                *bits |= U256::from(1) << bit;
+            }
+        }
+        if tick_lower <= self.tick && self.tick < tick_upper {
+            self.liquidity = add_delta(self.liquidity, delta).unwrap_or(0);
+        }
+    }
+
+    /// Exact-input swap, tick by tick, as `UniswapV3Pool.swap` computes it.
+    ///
+    /// Returns `None` if the input can't be filled within the loaded ticks
+    /// (or at all), so callers never price on a guessed liquidity curve.
+    pub fn swap(&self, zero_for_one: bool, amount_in: U256) -> Option<U256> {
+        if amount_in.is_zero() {
+            return None;
+        }
+
+        let price_limit = if zero_for_one {
+            MIN_SQRT_RATIO + U256::from(1)
+        } else {
+            MAX_SQRT_RATIO - U256::from(1)
+        };
+
+        let mut remaining = amount_in;
+        let mut amount_out = U256::ZERO;
+        let mut sqrt_price = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+
+        while !remaining.is_zero() && sqrt_price != price_limit {
+            let (tick_next, initialized) = self.next_initialized_tick(tick, zero_for_one)?;
+            let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_next = sqrt_ratio_at_tick(tick_next)?;
+
+            let target = if zero_for_one {
+                sqrt_next.max(price_limit)
+            } else {
+                sqrt_next.min(price_limit)
+            };
+
+            let (next_price, step_in, step_out, fee_amount) =
+                compute_swap_step(sqrt_price, target, liquidity, remaining, self.fee)?;
+            sqrt_price = next_price;
+            remaining = remaining.checked_sub(step_in.checked_add(fee_amount)?)?;
+            amount_out = amount_out.checked_add(step_out)?;
+
+            if sqrt_price != sqrt_next {
+                // stopped inside the range: input used up (or limit hit)
+                break;
+            }
+
+            if initialized {
+                let mut net = *self.ticks.get(&tick_next)?;
+                if zero_for_one {
+                    net = net.checked_neg()?;
+                }
+                liquidity = add_delta(liquidity, net)?;
+            }
+            tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        }
+
+        if !remaining.is_zero() {
+            return None;
+        }
+        Some(amount_out)
+    }
+
+    /// `TickBitmap.nextInitializedTickWithinOneWord` over the loaded words.
+    fn next_initialized_tick(&self, tick: i32, lte: bool) -> Option<(i32, bool)> {
+        let spacing = self.tick_spacing;
+        let mut compressed = tick / spacing;
+        if tick < 0 && tick % spacing != 0 {
+            compressed -= 1;
+        }
+
+        if lte {
+            let (word_pos, bit_pos) = bitmap_position(compressed);
+            let word = self.tick_bitmap.get(&word_pos)?;
+            let mask = (U256::from(1) << bit_pos) - U256::from(1) + (U256::from(1) << bit_pos);
+            let masked = *word & mask;
+            let initialized = !masked.is_zero();
+            let next = if initialized {
+                let msb = 255 - masked.leading_zeros() as i32;
+                (compressed - (bit_pos as i32 - msb)) * spacing
+            } else {
+                (compressed - bit_pos as i32) * spacing
+            };
+            Some((next, initialized))
+        } else {
+            let (word_pos, bit_pos) = bitmap_position(compressed + 1);
+            let word = self.tick_bitmap.get(&word_pos)?;
+            let mask = !((U256::from(1) << bit_pos) - U256::from(1));
+            let masked = *word & mask;
+            let initialized = !masked.is_zero();
+            let next = if initialized {
+                let lsb = masked.trailing_zeros() as i32;
+                (compressed + 1 + (lsb - bit_pos as i32)) * spacing
+            } else {
+                (compressed + 1 + (255 - bit_pos as i32)) * spacing
+            };
+            Some((next, initialized))
+        }
+    }
+}
+
+impl AmmPool for V3Pool {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::UniswapV3
+    }
+
+    fn tokens(&self) -> Vec<&Token> {
+        vec![&self.token0, &self.token1]
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee_fraction()
+    }
+
+    /// Mid-price from `sqrtPriceX96`, less the fee; unpriced without
+    /// in-range liquidity
+    fn spot_rate(&self, token_in: &str, token_out: &str) -> Option<f64> {
+        if self.liquidity == 0 {
+            return None;
+        }
+        let mid = self.mid_price_0to1();
+        let rate = if token_in == self.token0.id && token_out == self.token1.id {
+            mid
+        } else if token_in == self.token1.id && token_out == self.token0.id {
+            1.0 / mid
+        } else {
+            return None;
+        };
+        let rate = (1.0 - self.fee_fraction()) * rate;
+        (rate.is_finite() && rate > 0.0).then_some(rate)
+    }
+
+    /// Virtual reserves of the active range: L/√P of token0, L·√P of
+    /// token1
+    fn holdings(&self) -> Option<Vec<f64>> {
+        let sqrt_price = f64::from(self.sqrt_price_x96) / 2f64.powi(96);
+        if !(sqrt_price.is_finite() && sqrt_price > 0.0) {
+            return None;
+        }
+        let liquidity = self.liquidity as f64;
+        Some(vec![
+            whole_tokens(liquidity / sqrt_price, &self.token0),
+            whole_tokens(liquidity * sqrt_price, &self.token1),
+        ])
+    }
+
+    fn quote(&self, amount_in: U256, token_in: &str, token_out: &str) -> Option<U256> {
+        if token_in == self.token0.id && token_out == self.token1.id {
+            self.swap(true, amount_in)
+        } else if token_in == self.token1.id && token_out == self.token0.id {
+            self.swap(false, amount_in)
+        } else {
+            None
+        }
+    }
+}
+
+fn bitmap_position(compressed: i32) -> (i16, usize) {
+    ((compressed >> 8) as i16, (compressed & 0xff) as usize)
+}
+
+/// -------------------------------
+/// Core math (TickMath, SqrtPriceMath, SwapMath ports)
+/// -------------------------------
+fn q96() -> U256 {
+    U256::from(1) << 96
+}
+
+/// FullMath.mulDiv: floor(a·b / d) with a 512-bit intermediate
+fn mul_div(a: U256, b: U256, d: U256) -> Option<U256> {
+    if d.is_zero() {
+        return None;
+    }
+    let product: U512 = a.widening_mul(b);
+    U256::uint_try_from(product / U512::from(d)).ok()
+}
+
+/// FullMath.mulDivRoundingUp
+fn mul_div_rounding_up(a: U256, b: U256, d: U256) -> Option<U256> {
+    let result = mul_div(a, b, d)?;
+    let product: U512 = a.widening_mul(b);
+    if (product % U512::from(d)).is_zero() {
+        Some(result)
+    } else {
+        result.checked_add(U256::from(1))
+    }
+}
+
+/// UnsafeMath.divRoundingUp
+fn div_rounding_up(a: U256, b: U256) -> U256 {
+    let q = a / b;
+    if (a % b).is_zero() {
+        q
+    } else {
+        q + U256::from(1)
+    }
+}
+
+fn add_delta(liquidity: u128, delta: i128) -> Option<u128> {
+    if delta < 0 {
+        liquidity.checked_sub(delta.unsigned_abs())
+    } else {
+        liquidity.checked_add(delta as u128)
+    }
+}
+
+/// TickMath.getSqrtRatioAtTick
+pub fn sqrt_ratio_at_tick(tick: i32) -> Option<U256> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return None;
+    }
+    let abs_tick = tick.unsigned_abs();
+
+    const FACTORS: [u128; 19] = [
+        0xfff97272373d413259a46990580e213a,
+        0xfff2e50f5f656932ef12357cf3c7fdcc,
+        0xffe5caca7e10e4e61c3624eaa0941cd0,
+        0xffcb9843d60f6159c9db58835c926644,
+        0xff973b41fa98c081472e6896dfb254c0,
+        0xff2ea16466c96a3843ec78b326b52861,
+        0xfe5dee046a99a2a811c461f1969c3053,
+        0xfcbe86c7900a88aedcffc83b479aa3a4,
+        0xf987a7253ac413176f2b074cf7815e54,
+        0xf3392b0822b70005940c7a398e4b70f3,
+        0xe7159475a2c29b7443b29c7fa6e889d9,
+        0xd097f3bdfd2022b8845ad8f792aa5825,
+        0xa9f746462d870fdf8a65dc1f90e061e5,
+        0x70d869a156d2a1b890bb3df62baf32f7,
+        0x31be135f97d08fd981231505542fcfa6,
+        0x9aa508b5b7a84e1c677de54f3e99bc9,
+        0x5d6af8dedb81196699c329225ee604,
+        0x2216e584f5fa1ea926041bedfe98,
+        0x48a170391f7dc42444e8fa2,
+    ];
+
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001_u128)
+    } else {
+        U256::from(1) << 128
+    };
+    for (i, f) in FACTORS.iter().enumerate() {
+        if abs_tick & (1 << (i + 1)) != 0 {
+            ratio = (ratio * U256::from(*f)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // round up to Q64.96
+    let rem: U256 = ratio % (U256::from(1) << 32);
+    Some(
+        (ratio >> 32)
+            + if rem.is_zero() {
+                U256::ZERO
+            } else {
+                U256::from(1)
+            },
+    )
+}
+
+/// SqrtPriceMath.getAmount0Delta
+fn amount0_delta(a: U256, b: U256, liquidity: u128, round_up: bool) -> Option<U256> {
+    let (lo, hi) = if a > b { (b, a) } else { (a, b) };
+    if lo.is_zero() {
+        return None;
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = hi - lo;
+    if round_up {
+        Some(div_rounding_up(
+            mul_div_rounding_up(numerator1, numerator2, hi)?,
+            lo,
+        ))
+    } else {
+        Some(mul_div(numerator1, numerator2, hi)? / lo)
+    }
+}
+
+/// SqrtPriceMath.getAmount1Delta
+fn amount1_delta(a: U256, b: U256, liquidity: u128, round_up: bool) -> Option<U256> {
+    let (lo, hi) = if a > b { (b, a) } else { (a, b) };
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), hi - lo, q96())
+    } else {
+        mul_div(U256::from(liquidity), hi - lo, q96())
+    }
+}
+
+/// SqrtPriceMath.getNextSqrtPriceFromInput
+fn next_sqrt_price_from_input(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Option<U256> {
+    if sqrt_price.is_zero() || liquidity == 0 {
+        return None;
+    }
+    if amount_in.is_zero() {
+        return Some(sqrt_price);
+    }
+
+    if zero_for_one {
+        // getNextSqrtPriceFromAmount0RoundingUp(add = true)
+        let numerator1: U256 = U256::from(liquidity) << 96;
+        if let Some(product) = amount_in.checked_mul(sqrt_price)
+            && let Some(denominator) = numerator1.checked_add(product)
+        {
+            return mul_div_rounding_up(numerator1, sqrt_price, denominator);
+        }
+        Some(div_rounding_up(
+            numerator1,
+            (numerator1 / sqrt_price).checked_add(amount_in)?,
+        ))
+    } else {
+        // getNextSqrtPriceFromAmount1RoundingDown(add = true)
+        let quotient = if amount_in <= (U256::from(1) << 160) - U256::from(1) {
+            (amount_in << 96) / U256::from(liquidity)
+        } else {
+            mul_div(amount_in, q96(), U256::from(liquidity))?
+        };
+        sqrt_price.checked_add(quotient)
+    }
+}
+
+/// SwapMath.computeSwapStep for exact input.
+///
+/// Returns `(sqrt_price_next, amount_in, amount_out, fee_amount)`.
+fn compute_swap_step(
+    sqrt_current: U256,
+    sqrt_target: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    fee_pips: u32,
+) -> Option<(U256, U256, U256, U256)> {
+    let zero_for_one = sqrt_current >= sqrt_target;
+    let fee = U256::from(fee_pips);
+    let denom = U256::from(FEE_PIPS_DENOMINATOR);
+
+    let remaining_less_fee = mul_div(amount_remaining, denom - fee, denom)?;
+    let mut amount_in = if zero_for_one {
+        amount0_delta(sqrt_target, sqrt_current, liquidity, true)?
+    } else {
+        amount1_delta(sqrt_current, sqrt_target, liquidity, true)?
+    };
+
+    let sqrt_next = if remaining_less_fee >= amount_in {
+        sqrt_target
+    } else {
+        next_sqrt_price_from_input(sqrt_current, liquidity, remaining_less_fee, zero_for_one)?
+    };
+    let max = sqrt_target == sqrt_next;
+
+    let amount_out = if zero_for_one {
+        if !max {
+            amount_in = amount0_delta(sqrt_next, sqrt_current, liquidity, true)?;
+        }
+        amount1_delta(sqrt_next, sqrt_current, liquidity, false)?
+    } else {
+        if !max {
+            amount_in = amount1_delta(sqrt_current, sqrt_next, liquidity, true)?;
+        }
+        amount0_delta(sqrt_current, sqrt_next, liquidity, false)?
+    };
+
+    let fee_amount = if !max {
+        amount_remaining.checked_sub(amount_in)?
+    } else {
+        mul_div_rounding_up(amount_in, fee, denom - fee)?
+    };
+
+    Some((sqrt_next, amount_in, amount_out, fee_amount))
+}
+
+/// -------------------------------
+/// On-chain loading (multicall)
+/// -------------------------------
+/// Find V3 pools for the given token pairs across every fee tier, as of
+/// `block`.
+pub async fn discover_v3_pools<P>(
+    provider: Arc<P>,
+    pairs: &[(Address, Address)],
+    block: BlockId,
+) -> Result<Vec<Address>>
+where
+    P: Provider + 'static,
+{
+    let multicall = IMulticall2::new(MULTICALL2, provider.clone());
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+
+    let queries: Vec<(Address, Address, u32)> = pairs
+        .iter()
+        .flat_map(|&(a, b)| V3_FEE_TIERS.iter().map(move |&fee| (a, b, fee)))
+        .collect();
+
+    for chunk in queries.chunks(ADDR_BATCH) {
+        let calls: Vec<Call> = chunk
+            .iter()
+            .map(|&(a, b, fee)| Call {
+                target: UNISWAP_V3_FACTORY,
+                callData: IUniswapV3Factory::getPoolCall {
+                    tokenA: a,
+                    tokenB: b,
+                    fee: U24::from(fee),
+                }
+                .abi_encode()
+                .into(),
+            })
+            .collect();
+        let res = multicall.aggregate(calls).block(block).call().await?;
+        for raw in res.returnData {
+            let pool = IUniswapV3Factory::getPoolCall::abi_decode_returns(&raw)?;
+            if pool != Address::ZERO && seen.insert(pool) {
+                found.push(pool);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Load slot0, liquidity, fee, spacing and the ticks around the current
+/// price for every pool in `pool_addrs`, all read at `block`.
+pub async fn fetch_v3_pools<P>(
+    provider: Arc<P>,
+    pool_addrs: &[Address],
+    cache: Arc<DashMap<Address, Token>>,
+    block: BlockId,
+) -> Result<Vec<V3Pool>>
+where
+    P: Provider + 'static,
+{
+    let multicall = IMulticall2::new(MULTICALL2, provider.clone());
+    let mut pools = Vec::new();
+
+    for chunk in pool_addrs.chunks(ADDR_BATCH) {
+        // 1. static config + current state
+        let mut calls = Vec::with_capacity(chunk.len() * 6);
+        for &pool in chunk {
+            for data in [
+                IUniswapV3Pool::token0Call.abi_encode(),
+                IUniswapV3Pool::token1Call.abi_encode(),
+                IUniswapV3Pool::feeCall.abi_encode(),
+                IUniswapV3Pool::tickSpacingCall.abi_encode(),
+                IUniswapV3Pool::liquidityCall.abi_encode(),
+                IUniswapV3Pool::slot0Call.abi_encode(),
+            ] {
+                calls.push(Call {
+                    target: pool,
+                    callData: data.into(),
+                });
+            }
+        }
+        let res = multicall.aggregate(calls).block(block).call().await?;
+
+        // resolve every token of the batch in one go before building pools
+        let mut token_addrs = Vec::with_capacity(chunk.len() * 2);
+        for r in res.returnData.chunks(6) {
+            token_addrs.push(IUniswapV3Pool::token0Call::abi_decode_returns(&r[0])?);
+            token_addrs.push(IUniswapV3Pool::token1Call::abi_decode_returns(&r[1])?);
+        }
+        load_tokens(provider.clone(), &token_addrs, &cache, block).await?;
+
+        let mut loaded = Vec::with_capacity(chunk.len());
+        for (i, &pool) in chunk.iter().enumerate() {
+            let r = &res.returnData[i * 6..i * 6 + 6];
+            let token0 = IUniswapV3Pool::token0Call::abi_decode_returns(&r[0])?;
+            let token1 = IUniswapV3Pool::token1Call::abi_decode_returns(&r[1])?;
+            let fee: U24 = IUniswapV3Pool::feeCall::abi_decode_returns(&r[2])?;
+            let spacing: I24 = IUniswapV3Pool::tickSpacingCall::abi_decode_returns(&r[3])?;
+            let liquidity: u128 = IUniswapV3Pool::liquidityCall::abi_decode_returns(&r[4])?;
+            let slot0 = IUniswapV3Pool::slot0Call::abi_decode_returns(&r[5])?;
+
+            let t0 = match load_token(token0, provider.clone(), &cache, block).await {
+                Ok(t) => t,
+                Err(e) => {
+                    eprintln!("Error loading token0 of {:#x}: {:?}", pool, e);
+                    continue;
+                }
+            };
+            let t1 = match load_token(token1, provider.clone(), &cache, block).await {
+                Ok(t) => t,
+                Err(e) => {
+                    eprintln!("Error loading token1 of {:#x}: {:?}", pool, e);
+                    continue;
+                }
+            };
+
+            loaded.push((
+                pool,
+                V3Pool {
+                    id: format!("{:#x}", pool),
+                    token0: t0,
+                    token1: t1,
+                    fee: fee.to::<u32>(),
+                    tick_spacing: spacing.as_i32(),
+                    sqrt_price_x96: U256::from(slot0.sqrtPriceX96),
+                    tick: slot0.tick.as_i32(),
+                    liquidity,
+                    tick_bitmap: HashMap::new(),
+                    ticks: BTreeMap::new(),
+                },
+            ));
+        }
+
+        // 2. bitmap words around the current tick
+        let mut word_calls = Vec::new();
+        let mut word_keys = Vec::new();
+        for (addr, p) in &loaded {
+            let mut compressed = p.tick / p.tick_spacing;
+            if p.tick < 0 && p.tick % p.tick_spacing != 0 {
+                compressed -= 1;
+            }
+            let (center, _) = bitmap_position(compressed);
+            for w in center.saturating_sub(BITMAP_WORDS_EACH_SIDE)
+                ..=center.saturating_add(BITMAP_WORDS_EACH_SIDE)
+            {
+                word_calls.push(Call {
+                    target: *addr,
+                    callData: IUniswapV3Pool::tickBitmapCall { wordPosition: w }
+                        .abi_encode()
+                        .into(),
+                });
+                word_keys.push((*addr, w));
+            }
+        }
+        let words = multicall
+            .aggregate(word_calls)
+            .block(block)
+            .call()
+            .await?
+            .returnData;
+
+        // 3. liquidityNet for every initialized tick in those words
+        let mut tick_calls = Vec::new();
+        let mut tick_keys = Vec::new();
+        for ((addr, w), raw) in word_keys.into_iter().zip(words.iter()) {
+            let word: U256 = IUniswapV3Pool::tickBitmapCall::abi_decode_returns(raw)?;
+            let Some((_, p)) = loaded.iter_mut().find(|(a, _)| *a == addr) else {
+                continue;
+            };
+            p.tick_bitmap.insert(w, word);
+            for bit in 0..256usize {
+                if word.bit(bit) {
+                    let tick = ((w as i32) * 256 + bit as i32) * p.tick_spacing;
+                    tick_calls.push(Call {
+                        target: addr,
+                        callData: IUniswapV3Pool::ticksCall {
+                            tick: I24::try_from(tick)?,
+                        }
+                        .abi_encode()
+                        .into(),
+                    });
+                    tick_keys.push((addr, tick));
+                }
+            }
+        }
+        for (calls, keys) in tick_calls
+            .chunks(ADDR_BATCH * 6)
+            .zip(tick_keys.chunks(ADDR_BATCH * 6))
+        {
+            let res = multicall
+                .aggregate(calls.to_vec())
+                .block(block)
+                .call()
+                .await?;
+            for ((addr, tick), raw) in keys.iter().zip(res.returnData.iter()) {
+                let info = IUniswapV3Pool::ticksCall::abi_decode_returns(raw)?;
+                if let Some((_, p)) = loaded.iter_mut().find(|(a, _)| a == addr) {
+                    p.ticks.insert(*tick, info.liquidityNet);
+                }
+            }
+        }
+
+        pools.extend(loaded.into_iter().map(|(_, p)| p));
+    }
+
+    Ok(pools)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::token;
+
+    /// Pool at tick 0 with one position on [lower, upper] and, optionally,
+    /// a second one starting at `extra_from` going up.
+    fn pool(liquidity: u128, lower: i32, upper: i32, extra: Option<(i32, u128)>) -> V3Pool {
+        let spacing = 60;
+        let mut ticks = BTreeMap::new();
+        ticks.insert(lower, liquidity as i128);
+        ticks.insert(upper, -(liquidity as i128));
+        if let Some((from, l)) = extra {
+            *ticks.entry(from).or_insert(0) += l as i128;
+            *ticks.entry(upper).or_insert(0) -= l as i128;
+        }
+
+        let mut tick_bitmap: HashMap<i16, U256> = HashMap::new();
+        for w in -4..=4 {
+            tick_bitmap.insert(w, U256::ZERO);
+        }
+        for &t in ticks.keys() {
+            let (w, b) = bitmap_position(t / spacing);
+            *tick_bitmap.get_mut(&w).unwrap() |= U256::from(1) << b;
+        }
+
+        V3Pool {
+            id: "v3".to_string(),
+            token0: token("a"),
+            token1: token("b"),
+            fee: 3000,
+            tick_spacing: spacing,
+            sqrt_price_x96: sqrt_ratio_at_tick(0).unwrap(),
+            tick: 0,
+            liquidity,
+            tick_bitmap,
+            ticks,
+        }
+    }
+
+    #[test]
+    fn tick_math_bounds() {
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK), Some(MIN_SQRT_RATIO));
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK), Some(MAX_SQRT_RATIO));
+        assert_eq!(sqrt_ratio_at_tick(0), Some(q96()));
+        assert_eq!(
+            sqrt_ratio_at_tick(1),
+            Some(U256::from(79232123823359799118286999568_u128))
+        );
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK + 1), None);
+    }
+
+    #[test]
+    fn small_swap_matches_mid_price_less_fee() {
+        let p = pool(10u128.pow(24), -6000, 6000, None);
+        let amount = U256::from(10u64.pow(15));
+        let out = p.swap(true, amount).unwrap();
+        let expected = 1e15 * (1.0 - p.fee_fraction()) * p.mid_price_0to1();
+        let got = f64::from(out);
+        assert!(
+            (got - expected).abs() / expected < 1e-6,
+            "{got} vs {expected}"
+        );
+    }
+
+    #[test]
+    fn crossing_into_more_liquidity_pays_more() {
+        let thin = pool(10u128.pow(21), -6000, 6000, None);
+        let thick = pool(10u128.pow(21), -6000, 6000, Some((120, 10u128.pow(23))));
+
+        // a buy of token0 large enough to push the price past tick 120
+        let amount = U256::from(10u128.pow(20));
+        let out_thin = thin.swap(false, amount).unwrap();
+        let out_thick = thick.swap(false, amount).unwrap();
+        assert!(out_thick > out_thin);
+
+        // below the crossing both pools quote the same
+        let small = U256::from(10u128.pow(17));
+        assert_eq!(thin.swap(false, small), thick.swap(false, small));
+    }
+
+    #[test]
+    fn swap_beyond_liquidity_is_unquotable() {
+        let p = pool(10u128.pow(18), -60, 60, None);
+        assert_eq!(p.swap(true, U256::from(10u128.pow(30))), None);
+    }
+
+    #[test]
+    fn liquidity_delta_checks_underflow() {
+        assert_eq!(add_delta(10, -3), Some(7));
+        assert_eq!(add_delta(1, -3), None);
+    }
+}